@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::context::AppContext;
+use crate::markdown::ParsedMarkdown;
+use crate::services::blue_sky::comments;
+use crate::services::site_generator::{Comments, Layout, Link, SeriesNav, SiteGenerator};
+use crate::shortcodes::{self, ShortcodeContext};
+use crate::utils::html::escape_html;
+
+/// Writes the pages assembled on the site generator's last update to the
+/// output directory.
+pub async fn build(cx: &AppContext) -> Result<()> {
+    info!("Generating site");
+    if let Err(e) = cx.site_generator().read().await.generate(cx).await {
+        error!("Failed to generate site: {:?}", e);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Renders every page in memory without writing anything, so broken content
+/// or templates are caught before a build.
+pub async fn check(cx: &AppContext) -> Result<()> {
+    cx.site_generator().read().await.check(cx).await
+}
+
+/// Removes the output directory and the build cache.
+pub fn clean(cx: &AppContext) -> Result<()> {
+    for dir in [cx.output_dir(), cx.cache_dir()] {
+        if dir.is_dir() {
+            info!("Removing {:?}", dir);
+            fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {:?}", dir))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the site generator's pages with ones built from the loaded
+/// content.
+pub async fn add_pages(site_generator: &mut SiteGenerator, cx: &AppContext) -> Result<()> {
+    site_generator.clear_pages();
+
+    // Generate index page
+    let content_sources = cx.content_sources().read().await;
+    let posts = content_sources.posts_collection().posts();
+
+    info!("Found {} posts", posts.len());
+
+    let date_format = site_generator.date_format().to_string();
+    let site = &cx.config().site;
+
+    // Shortcode handlers may take the Bluesky lock themselves, so expand
+    // them before holding it below
+    let shortcode_cx = ShortcodeContext {
+        app: cx,
+        date_format: &date_format,
+    };
+    let mut post_html = HashMap::new();
+    for post in &posts {
+        let html = shortcodes::expand(&post.html_content, &post.shortcodes, &shortcode_cx)
+            .await
+            .with_context(|| format!("Failed to expand shortcodes in {:?}", post.path))?;
+        post_html.insert(post.path.clone(), html);
+    }
+
+    let blue_sky = cx.blue_sky().read().await;
+    let bluesky_config = &cx.config().bluesky;
+    let latest_notes = blue_sky.render_posts(
+        bluesky_config,
+        Some(bluesky_config.index_posts),
+        &date_format,
+    );
+    let latest_notes = if latest_notes.is_empty() {
+        Vec::new()
+    } else {
+        let mut section = vec!["<h2>Notes</h2>".to_string()];
+        section.extend(latest_notes);
+        section.push("<p><a href='notes.html'>All notes</a></p>".to_string());
+        section
+    };
+
+    let index_page = site_generator
+        .new_page(Layout::Index)
+        .title("hey ✌🏽")
+        .slug("index")
+        .child(thin_column(&site.intro))
+        .child("<h2>Posts</h2>")
+        .children(
+            posts
+                .iter()
+                .map(|post| post_list_item(post, "", &date_format)),
+        )
+        .child("<p><a href='tags/index.html'>Browse posts by tag</a></p>")
+        .children(latest_notes)
+        .child(thin_column(&site.colophon))
+        .build();
+
+    site_generator.add_page(index_page);
+
+    let notes_page = site_generator
+        .new_page(Layout::Feed)
+        .title("Notes")
+        .slug("notes")
+        .children(blue_sky.render_posts(bluesky_config, None, &date_format))
+        .build();
+    site_generator.add_page(notes_page);
+
+    let series = content_sources.posts_collection().series();
+
+    for post in &posts {
+        let mut post_page = site_generator
+            .new_page(Layout::Page)
+            .title(post.front_matter.title.clone())
+            .slug(post.slug())
+            .date(post.front_matter.date)
+            .updated(post.front_matter.updated)
+            .tags(post.front_matter.tags.clone().unwrap_or_default())
+            .child(post_html[&post.path].clone());
+
+        if post.front_matter.toc {
+            post_page = post_page.toc(post.toc.clone());
+        }
+
+        if let Some(uri) = &post.front_matter.bluesky_uri {
+            let replies = blue_sky.comments(uri);
+            post_page = post_page.comments(Comments {
+                href: comments::thread_url(uri),
+                count: replies.iter().map(|reply| reply.count()).sum(),
+                html: replies
+                    .iter()
+                    .map(|reply| reply.render(&date_format))
+                    .collect(),
+            });
+        }
+
+        if let Some((series, index)) = series
+            .iter()
+            .find_map(|series| Some((series, series.position(post)?)))
+        {
+            let link = |post: &&ParsedMarkdown| Link {
+                title: post.front_matter.title.clone(),
+                href: format!("{}.html", post.slug()),
+            };
+            post_page = post_page.series(SeriesNav {
+                name: series.name.clone(),
+                href: format!("series/{}.html", series.slug),
+                part: index + 1,
+                total: series.posts.len(),
+                previous: index
+                    .checked_sub(1)
+                    .and_then(|i| series.posts.get(i))
+                    .map(link),
+                next: series.posts.get(index + 1).map(link),
+            });
+        }
+
+        site_generator.add_page(post_page.build());
+    }
+    site_generator.set_post_html(post_html);
+
+    // Generate series pages
+    for series in &series {
+        let series_page = site_generator
+            .new_page(Layout::Series)
+            .title(series.name.clone())
+            .slug(format!("series/{}", series.slug))
+            .children(
+                series
+                    .posts
+                    .iter()
+                    .map(|post| post_list_item(post, "../", &date_format)),
+            )
+            .build();
+        site_generator.add_page(series_page);
+    }
+
+    // Generate tag pages
+    let tags = content_sources.posts_collection().tags();
+
+    let tags_page = site_generator
+        .new_page(Layout::Tags)
+        .title("Tags")
+        .slug("tags/index")
+        .children(tags.iter().map(|tag| {
+            format!(
+                "<li><a href='{}.html'>#{}</a> ({})</li>",
+                tag.slug,
+                escape_html(&tag.name),
+                tag.posts.len()
+            )
+        }))
+        .build();
+    site_generator.add_page(tags_page);
+
+    for tag in &tags {
+        let tag_page = site_generator
+            .new_page(Layout::Tag)
+            .title(format!("#{}", tag.name))
+            .slug(format!("tags/{}", tag.slug))
+            .children(
+                tag.posts
+                    .iter()
+                    .map(|post| post_list_item(post, "../", &date_format)),
+            )
+            .build();
+        site_generator.add_page(tag_page);
+    }
+
+    Ok(())
+}
+
+/// Wraps a homepage blurb from the config, skipping it when unset.
+fn thin_column(html: &str) -> String {
+    if html.trim().is_empty() {
+        String::new()
+    } else {
+        format!("<div class='thin-column'>{}</div>", html)
+    }
+}
+
+/// Renders a post as a list item linking to it, relative to `root`.
+fn post_list_item(post: &ParsedMarkdown, root: &str, date_format: &str) -> String {
+    format!(
+        "<li><a href='{}{}.html'>{}</a> - <time datetime='{}'>{}</time></li>",
+        root,
+        post.slug(),
+        escape_html(&post.front_matter.title),
+        post.front_matter.date,
+        post.front_matter.date.format(date_format)
+    )
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const CACHE_FILE: &str = "build.json";
+
+/// What the previous build produced, used to skip pages whose inputs haven't
+/// changed since.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    /// Hash of every file in the templates and includes directories
+    pub template_hash: String,
+    /// Output path, relative to the output directory -> hash of the inputs
+    /// used to render it
+    pub pages: BTreeMap<String, String>,
+}
+
+impl BuildCache {
+    pub fn new(template_hash: String) -> Self {
+        Self {
+            template_hash,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the cache from `cache_dir`. A missing or unreadable cache is
+    /// treated as empty so the next build renders everything.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(CACHE_FILE);
+        let Ok(content) = fs::read_to_string(&path) else {
+            debug!("No build cache at {:?}", path);
+            return Self::default();
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable build cache {:?}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        fs::create_dir_all(cache_dir)?;
+        let path = cache_dir.join(CACHE_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write build cache {:?}", path))
+    }
+}
+
+/// Hashes the contents and relative paths of every file under `dirs`.
+pub fn hash_dirs(dirs: &[PathBuf]) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    for dir in dirs {
+        let mut files = Vec::new();
+        collect_files(dir, &mut files)?;
+        files.sort();
+
+        for file in files {
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(&fs::read(&file)?);
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
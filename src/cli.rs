@@ -0,0 +1,84 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::context::ContextOptions;
+use crate::server;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Builds the homebase static site")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Site config file [default: ./homebase.toml]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Directory containing content collections [default: ./content]
+    #[arg(long, global = true)]
+    pub content_dir: Option<PathBuf>,
+
+    /// Directory the site is written to [default: ./public]
+    #[arg(long, global = true)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Directory of static files copied into the output [default: ./src/includes]
+    #[arg(long, global = true)]
+    pub includes_dir: Option<PathBuf>,
+
+    /// Directory of layout templates [default: ./templates]
+    #[arg(long, global = true)]
+    pub templates_dir: Option<PathBuf>,
+
+    /// Don't fetch from Bluesky, build from the cached posts instead
+    #[arg(long, global = true)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Clone, Default, Subcommand)]
+pub enum Command {
+    /// Build the site into the output directory (the default)
+    #[default]
+    Build,
+    /// Build, serve the output directory on localhost and rebuild on changes
+    Serve {
+        #[arg(long, short, default_value_t = server::DEFAULT_PORT)]
+        port: u16,
+    },
+    /// Scaffold new content
+    #[command(subcommand)]
+    New(NewCommand),
+    /// Validate content and templates without writing anything
+    Check,
+    /// Remove the output directory and build cache
+    Clean,
+    /// Announce new blog posts on Bluesky and record the post URIs
+    Publish {
+        /// Print what would be posted without posting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Fetch older Bluesky posts until the full history is cached. Resumes
+    /// where the last run stopped
+    Backfill,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum NewCommand {
+    /// Create a markdown file and index.toml entry for a new post
+    Post { title: String },
+}
+
+impl Cli {
+    pub fn context_options(&self) -> ContextOptions {
+        ContextOptions {
+            config: self.config.clone(),
+            content_dir: self.content_dir.clone(),
+            output_dir: self.output_dir.clone(),
+            includes_dir: self.includes_dir.clone(),
+            templates_dir: self.templates_dir.clone(),
+            cache_dir: None,
+            offline: self.offline,
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use anyhow::{anyhow, Context, Result};
+use atrium_api::types::string::Handle;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::debug;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::feed::FeedOptions;
+use crate::markdown::{MarkdownExtensions, PostDate};
+use crate::services::blue_sky::DEFAULT_SERVICE;
+
+pub const CONFIG_FILE: &str = "homebase.toml";
+
+/// Environment variables that override a config key, checked after the file
+/// is loaded.
+const ENV_OVERRIDES: &[(&str, &str, &str)] = &[
+    ("HOMEBASE_TITLE", "site", "title"),
+    ("HOMEBASE_AUTHOR", "site", "author"),
+    ("HOMEBASE_BASE_URL", "site", "base_url"),
+    ("HOMEBASE_BLUESKY_HANDLE", "bluesky", "handle"),
+    ("HOMEBASE_BLUESKY_SERVICE", "bluesky", "service"),
+    ("HOMEBASE_CONTENT_DIR", "directories", "content"),
+    ("HOMEBASE_OUTPUT_DIR", "directories", "output"),
+    ("HOMEBASE_INCLUDES_DIR", "directories", "includes"),
+    ("HOMEBASE_TEMPLATES_DIR", "directories", "templates"),
+];
+
+/// Site wide settings from `homebase.toml`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub site: SiteMetadata,
+    pub bluesky: BlueSkyConfig,
+    pub directories: DirectoriesConfig,
+    pub markdown: MarkdownExtensions,
+    pub feed: FeedOptions,
+}
+
+/// Who the site is by and where it lives. Available to templates as `site`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteMetadata {
+    pub title: String,
+    pub author: String,
+    /// The public URL the site is deployed to
+    pub base_url: String,
+    /// HTML shown above the post list on the homepage
+    pub intro: String,
+    /// HTML shown below the post list on the homepage
+    pub colophon: String,
+    /// `strftime` style format dates are displayed with
+    pub date_format: String,
+}
+
+impl Default for SiteMetadata {
+    fn default() -> Self {
+        Self {
+            title: "nate butler".to_string(),
+            author: "nate butler".to_string(),
+            base_url: "https://iamnbutler.github.io/homebase/".to_string(),
+            intro: String::new(),
+            colophon: String::new(),
+            date_format: PostDate::DEFAULT_FORMAT.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlueSkyConfig {
+    /// The account whose posts are fetched
+    pub handle: String,
+    /// The XRPC endpoint to log in to and fetch from
+    pub service: String,
+    /// How many of the latest posts to show on the homepage, 0 to hide them
+    pub index_posts: usize,
+    /// Show posts by others that `handle` reposted
+    pub reposts: bool,
+    /// Show replies to other people's posts
+    pub replies: bool,
+    /// Fold self-replies into the post that started the thread, otherwise
+    /// only the first post of a thread is shown
+    pub threads: bool,
+    /// Show quote posts, with the quoted post as a card
+    pub quotes: bool,
+    /// Show link cards, otherwise posts are shown without them
+    pub link_cards: bool,
+    /// Ignore posts from before this date, like `2023-05-01`. Fetching and
+    /// backfill stop once they reach it.
+    pub since: Option<String>,
+    /// Only blog posts dated on or after this are announced by `publish`,
+    /// so turning it on doesn't announce the whole archive
+    pub announce_since: Option<String>,
+}
+
+impl Default for BlueSkyConfig {
+    fn default() -> Self {
+        Self {
+            handle: "nate.rip".to_string(),
+            service: DEFAULT_SERVICE.to_string(),
+            index_posts: 5,
+            reposts: false,
+            replies: false,
+            threads: true,
+            quotes: true,
+            link_cards: true,
+            since: None,
+            announce_since: None,
+        }
+    }
+}
+
+/// Directories relative to the config file. Command line flags take
+/// precedence over these.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DirectoriesConfig {
+    pub content: PathBuf,
+    pub output: PathBuf,
+    pub includes: PathBuf,
+    pub templates: PathBuf,
+}
+
+impl Default for DirectoriesConfig {
+    fn default() -> Self {
+        Self {
+            content: PathBuf::from("content"),
+            output: PathBuf::from("public"),
+            includes: PathBuf::from("src").join("includes"),
+            templates: PathBuf::from("templates"),
+        }
+    }
+}
+
+impl SiteConfig {
+    /// Loads the config at `path`, applies environment overrides and
+    /// validates the result. A missing file falls back to the defaults.
+    ///
+    /// Relative directories are resolved against the config file's directory.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with_env(path, |var| std::env::var(var).ok())
+    }
+
+    /// `load`, reading environment overrides through `env`.
+    fn load_with_env(path: &Path, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => Some(source),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("No config at {:?}, using defaults", path);
+                None
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+
+        let mut config: SiteConfig = match &source {
+            Some(source) => {
+                toml::from_str(source).with_context(|| format!("Failed to parse {:?}", path))?
+            }
+            None => SiteConfig::default(),
+        };
+
+        let mut overridden = Vec::new();
+        for (var, table, key) in ENV_OVERRIDES {
+            if let Some(value) = env(var) {
+                config.set(table, key, value);
+                overridden.push((*table, *key, *var));
+            }
+        }
+
+        let location = |table: &str, key: &str| match overridden
+            .iter()
+            .find(|(t, k, _)| *t == table && *k == key)
+        {
+            Some((_, _, var)) => format!("${}", var),
+            None => key_location(path, source.as_deref().unwrap_or_default(), table, key),
+        };
+        config.validate(location)?;
+
+        let root = path.parent().unwrap_or(Path::new("."));
+        config.directories.resolve(root);
+
+        Ok(config)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: String) {
+        match (table, key) {
+            ("site", "title") => self.site.title = value,
+            ("site", "author") => self.site.author = value,
+            ("site", "base_url") => self.site.base_url = value,
+            ("bluesky", "handle") => self.bluesky.handle = value,
+            ("bluesky", "service") => self.bluesky.service = value,
+            ("directories", "content") => self.directories.content = value.into(),
+            ("directories", "output") => self.directories.output = value.into(),
+            ("directories", "includes") => self.directories.includes = value.into(),
+            ("directories", "templates") => self.directories.templates = value.into(),
+            _ => unreachable!("unknown config key {}.{}", table, key),
+        }
+    }
+
+    /// Checks the values serde can't, `location` describes where a key was
+    /// set for error messages.
+    fn validate(&self, location: impl Fn(&str, &str) -> String) -> Result<()> {
+        for (key, value) in [("title", &self.site.title), ("author", &self.site.author)] {
+            if value.trim().is_empty() {
+                return Err(anyhow!(
+                    "{}: `site.{}` can't be empty",
+                    location("site", key),
+                    key
+                ));
+            }
+        }
+
+        if StrftimeItems::new(&self.site.date_format).any(|item| item == Item::Error) {
+            return Err(anyhow!(
+                "{}: invalid `site.date_format` {:?}, expected a strftime format like \"%B %-d, %Y\"",
+                location("site", "date_format"),
+                self.site.date_format
+            ));
+        }
+
+        for (table, key, value) in [
+            ("site", "base_url", &self.site.base_url),
+            ("bluesky", "service", &self.bluesky.service),
+        ] {
+            let url = Url::parse(value).with_context(|| {
+                format!(
+                    "{}: invalid `{}.{}` {:?}",
+                    location(table, key),
+                    table,
+                    key,
+                    value
+                )
+            })?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(anyhow!(
+                    "{}: invalid `{}.{}` {:?}, expected an http or https URL",
+                    location(table, key),
+                    table,
+                    key,
+                    value
+                ));
+            }
+        }
+
+        for (key, value) in [
+            ("since", &self.bluesky.since),
+            ("announce_since", &self.bluesky.announce_since),
+        ] {
+            if let Some(value) = value {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d").with_context(|| {
+                    format!(
+                        "{}: invalid `bluesky.{}` {:?}, expected a date like `2023-05-01`",
+                        location("bluesky", key),
+                        key,
+                        value
+                    )
+                })?;
+            }
+        }
+
+        if self.feed.limit == Some(0) {
+            return Err(anyhow!(
+                "{}: `feed.limit` should be at least 1, leave it out to include every post",
+                location("feed", "limit")
+            ));
+        }
+
+        Handle::new(self.bluesky.handle.clone()).map_err(|e| {
+            anyhow!(
+                "{}: invalid `bluesky.handle` {:?}: {}",
+                location("bluesky", "handle"),
+                self.bluesky.handle,
+                e
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// The validated base URL.
+    pub fn base_url(&self) -> Url {
+        Url::parse(&self.site.base_url).expect("base_url is validated on load")
+    }
+}
+
+impl BlueSkyConfig {
+    /// The validated `since` date, as midnight UTC.
+    pub fn since(&self) -> Option<DateTime<Utc>> {
+        parse_validated_date(self.since.as_deref()?)
+    }
+
+    /// The validated `announce_since` date, as midnight UTC.
+    pub fn announce_since(&self) -> Option<DateTime<Utc>> {
+        parse_validated_date(self.announce_since.as_deref()?)
+    }
+}
+
+impl DirectoriesConfig {
+    fn resolve(&mut self, root: &Path) {
+        for dir in [
+            &mut self.content,
+            &mut self.output,
+            &mut self.includes,
+            &mut self.templates,
+        ] {
+            if dir.is_relative() {
+                *dir = root.join(&*dir);
+            }
+        }
+    }
+}
+
+fn parse_validated_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").expect("dates are validated on load");
+    Some(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
+
+/// Returns `path:line` for the line defining `key` inside `[table]`, or just
+/// the path if the key can't be found.
+fn key_location(path: &Path, source: &str, table: &str, key: &str) -> String {
+    let mut in_table = false;
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim_start();
+        if line.starts_with('[') {
+            in_table = line.trim_end().trim_matches(['[', ']']).trim() == table;
+        } else if in_table
+            && line
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        {
+            return format!("{}:{}", path.display(), index + 1);
+        }
+    }
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedContent;
+    use crate::utils::test_dir;
+    use tempfile::TempDir;
+
+    /// Writes `source` to a `homebase.toml` in a new temporary directory.
+    fn config_file(source: &str) -> (TempDir, PathBuf) {
+        let dir = test_dir();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, source).unwrap();
+        (dir, path)
+    }
+
+    fn load(path: &Path) -> Result<SiteConfig> {
+        SiteConfig::load_with_env(path, |_| None)
+    }
+
+    #[test]
+    fn missing_config_uses_defaults() {
+        let dir = test_dir();
+        let dir = dir.path();
+        let config = load(&dir.join(CONFIG_FILE)).unwrap();
+
+        assert_eq!(config.site.title, SiteMetadata::default().title);
+        assert_eq!(config.site.date_format, PostDate::DEFAULT_FORMAT);
+        assert_eq!(config.bluesky.handle, BlueSkyConfig::default().handle);
+        assert_eq!(config.feed.content, FeedContent::Full);
+        assert_eq!(config.feed.limit, None);
+        assert_eq!(config.directories.content, dir.join("content"));
+        assert_eq!(config.directories.output, dir.join("public"));
+    }
+
+    #[test]
+    fn reads_tables_and_resolves_directories() {
+        let (_dir, path) = config_file(
+            "[site]\ntitle = \"Field notes\"\ndate_format = \"%Y-%m-%d\"\n\n[directories]\noutput = \"dist\"\n\n[feed]\ncontent = \"excerpt\"\nlimit = 10\n",
+        );
+        let config = load(&path).unwrap();
+
+        assert_eq!(config.site.title, "Field notes");
+        assert_eq!(config.site.author, SiteMetadata::default().author);
+        assert_eq!(config.site.date_format, "%Y-%m-%d");
+        assert_eq!(
+            config.directories.output,
+            path.parent().unwrap().join("dist")
+        );
+        assert_eq!(config.feed.content, FeedContent::Excerpt);
+        assert_eq!(config.feed.limit, Some(10));
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_location() {
+        let (_dir, path) = config_file("[site]\ntitle = \"Field notes\"\ntagline = \"Notes\"\n");
+        let error = format!("{:#}", load(&path).unwrap_err());
+
+        assert!(error.starts_with(&format!("Failed to parse {:?}", path)));
+        assert!(error.contains("line 3"), "{}", error);
+        assert!(error.contains("unknown field `tagline`"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_values_are_reported_with_their_location() {
+        let error = |source| {
+            let (_dir, path) = config_file(source);
+            load(&path).unwrap_err().to_string()
+        };
+
+        assert!(
+            error("[site]\ntitle = \"Notes\"\nbase_url = \"ftp://example.com\"\n").ends_with(
+                ":3: invalid `site.base_url` \"ftp://example.com\", expected an http or https URL"
+            )
+        );
+        assert!(error("[site]\ndate_format = \"%Q\"\n")
+            .contains(":2: invalid `site.date_format` \"%Q\""));
+        assert!(error("[bluesky]\nsince = \"May 2023\"\n")
+            .contains(":2: invalid `bluesky.since` \"May 2023\""));
+        assert!(error("[feed]\n\nlimit = 0\n").contains(":3: `feed.limit` should be at least 1"));
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let (_dir, path) =
+            config_file("[site]\ntitle = \"From the file\"\nauthor = \"From the file\"\n");
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        let config = SiteConfig::load_with_env(
+            &path,
+            env(&[
+                ("HOMEBASE_TITLE", "From the environment"),
+                ("HOMEBASE_OUTPUT_DIR", "/srv/site"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.site.title, "From the environment");
+        assert_eq!(config.site.author, "From the file");
+        assert_eq!(config.directories.output, PathBuf::from("/srv/site"));
+
+        // Invalid overrides point at the variable rather than the file
+        let error = SiteConfig::load_with_env(&path, env(&[("HOMEBASE_BASE_URL", "not a url")]))
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("$HOMEBASE_BASE_URL: invalid `site.base_url`"));
+    }
+}
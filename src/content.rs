@@ -1 +1,3 @@
 pub mod posts;
+pub mod series;
+pub mod tags;
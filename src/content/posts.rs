@@ -1,14 +1,25 @@
-use anyhow::Result;
-use std::{fs, path::PathBuf};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use toml::Table;
 
-use crate::markdown::{slugify, FrontMatter, Markdown, ParsedMarkdown};
+use crate::markdown::{
+    slugify, FrontMatter, Markdown, MarkdownExtensions, MetaSource, ParsedMarkdown, PostDate,
+};
 use crate::services::content::Content;
 
+#[derive(Default)]
 pub struct PostsCollection {
     src: PathBuf,
     parsed_posts: Vec<ParsedMarkdown>,
     metadata: Table,
+    /// The raw text of `index.toml`, kept for error locations
+    metadata_source: String,
+    /// Site wide extensions, which posts can override
+    extensions: MarkdownExtensions,
 }
 
 impl Content for PostsCollection {
@@ -18,21 +29,31 @@ impl Content for PostsCollection {
 }
 
 impl PostsCollection {
-    pub fn new(src: PathBuf) -> Result<PostsCollection> {
+    pub fn new(src: PathBuf, extensions: MarkdownExtensions) -> Result<PostsCollection> {
         let mut collection = PostsCollection {
             src: src.clone(),
             parsed_posts: Vec::new(),
             metadata: Table::new(),
+            metadata_source: String::new(),
+            extensions,
         };
 
-        let metadata_path = src.join("index.toml");
-        let metadata_content = fs::read_to_string(metadata_path)?;
-        collection.metadata = metadata_content.parse::<Table>()?;
-
-        collection.parse_posts()?;
+        collection.reload()?;
         Ok(collection)
     }
 
+    /// Re-reads `index.toml` and re-parses every post.
+    pub fn reload(&mut self) -> Result<()> {
+        let metadata_path = self.src.join("index.toml");
+        let metadata_content = fs::read_to_string(&metadata_path)?;
+        self.metadata = metadata_content
+            .parse::<Table>()
+            .with_context(|| format!("Failed to parse {:?}", metadata_path))?;
+        self.metadata_source = metadata_content;
+
+        self.parse_posts()
+    }
+
     pub fn parse_posts(&mut self) -> Result<()> {
         self.parsed_posts.clear();
         for (year, posts) in self.metadata.iter() {
@@ -42,7 +63,9 @@ impl PostsCollection {
                     let file_path = year_dir.join(format!("{}.md", post_name));
                     if file_path.is_file() {
                         let content = fs::read_to_string(&file_path)?;
-                        let parsed_post = self.parse_post(post_meta, &content)?;
+                        let parsed_post = self
+                            .parse_post(year, post_name, &file_path, post_meta, &content)
+                            .with_context(|| format!("Failed to parse {:?}", file_path))?;
                         self.parsed_posts.push(parsed_post);
                     }
                 }
@@ -51,26 +74,275 @@ impl PostsCollection {
         Ok(())
     }
 
-    fn parse_post(&self, post_meta: &toml::Value, content: &str) -> Result<ParsedMarkdown> {
-        let mut front_matter: FrontMatter = post_meta.clone().try_into()?;
+    fn parse_post(
+        &self,
+        year: &str,
+        post_name: &str,
+        file_path: &Path,
+        post_meta: &toml::Value,
+        content: &str,
+    ) -> Result<ParsedMarkdown> {
+        let index_table = post_meta
+            .as_table()
+            .ok_or_else(|| anyhow!("Expected index.toml entry to be a table"))?;
+        let (first_line, section) = self.index_section(year, post_name).unwrap_or((1, ""));
+        let index_meta = MetaSource::new(
+            index_table.clone(),
+            self.src.join("index.toml"),
+            section,
+            first_line,
+        );
+
+        let (embedded, body) = Markdown::extract_front_matter(content)?;
+        let embedded_meta = embedded.map(|(table, raw)| {
+            MetaSource::new(
+                table,
+                file_path.to_path_buf(),
+                raw,
+                Markdown::FRONT_MATTER_FIRST_LINE,
+            )
+        });
+
+        let (mut front_matter, conflicts) =
+            FrontMatter::merge(Some(&index_meta), embedded_meta.as_ref())?;
+        for conflict in conflicts {
+            warn!(
+                "`{}` is {} at {} but {} at {}, using the front matter value",
+                conflict.key,
+                conflict.index_value,
+                conflict.index_location,
+                conflict.file_value,
+                conflict.file_location
+            );
+        }
 
         // Generate slug from title if not present
         if front_matter.slug.is_none() {
             front_matter.slug = Some(slugify(&front_matter.title));
         }
 
-        let html_content = Markdown::parse(content)?;
+        let extensions = self.extensions.overridden_by(&front_matter.markdown);
+        let first_line = content[..content.len() - body.len()].lines().count() + 1;
+        let rendered = Markdown::parse(body, first_line, &extensions)?;
 
         Ok(ParsedMarkdown {
             front_matter,
-            content: content.to_string(),
-            html_content,
+            content: rendered.markdown,
+            html_content: rendered.html,
+            toc: rendered.toc,
+            shortcodes: rendered.shortcodes,
+            path: file_path.to_path_buf(),
         })
     }
 
+    /// Returns the line number of the `[year."post name"]` header in
+    /// `index.toml` and the text of that table.
+    fn index_section(&self, year: &str, post_name: &str) -> Option<(usize, &str)> {
+        let header = format!("{}.{}", year, post_name);
+        let is_header = |line: &str| {
+            let line = line.trim();
+            line.starts_with('[')
+                && line
+                    .trim_matches(['[', ']'])
+                    .split('.')
+                    .map(|part| part.trim().trim_matches(['"', '\'']))
+                    .collect::<Vec<_>>()
+                    .join(".")
+                    == header
+        };
+
+        let mut offset = 0;
+        for (index, line) in self.metadata_source.split_inclusive('\n').enumerate() {
+            if is_header(line) {
+                let section = &self.metadata_source[offset..];
+                let end = section
+                    .split_inclusive('\n')
+                    .skip(1)
+                    .take_while(|line| !line.trim_start().starts_with('['))
+                    .map(str::len)
+                    .sum::<usize>()
+                    + line.len();
+                return Some((index + 1, &section[..end]));
+            }
+            offset += line.len();
+        }
+        None
+    }
+
+    /// Scaffolds a post: an empty markdown file under the year's directory
+    /// and a matching entry at the top of `index.toml`. Returns the path of
+    /// the markdown file.
+    pub fn create_post(src: &Path, title: &str, date: PostDate) -> Result<PathBuf> {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err(anyhow!("Post title can't be empty"));
+        }
+        // The title doubles as the file name, so it must stay a single path
+        // component inside the year's directory
+        if title.starts_with('.') || title.contains(['/', '\\']) || title.contains(char::is_control)
+        {
+            return Err(anyhow!(
+                "Post title {:?} can't start with '.' or contain path separators",
+                title
+            ));
+        }
+
+        let year = date.format("%Y");
+        let file_path = src.join(&year).join(format!("{}.md", title));
+        if file_path.exists() {
+            return Err(anyhow!("Post already exists: {:?}", file_path));
+        }
+
+        let metadata_path = src.join("index.toml");
+        let metadata_source = fs::read_to_string(&metadata_path)
+            .with_context(|| format!("Failed to read {:?}", metadata_path))?;
+        let metadata = metadata_source
+            .parse::<Table>()
+            .with_context(|| format!("Failed to parse {:?}", metadata_path))?;
+        if metadata
+            .get(&year)
+            .and_then(|posts| posts.get(title))
+            .is_some()
+        {
+            return Err(anyhow!(
+                "{:?} already has an entry for {:?}",
+                metadata_path,
+                title
+            ));
+        }
+
+        let title_value = toml::Value::String(title.to_string());
+        let entry = format!(
+            "[{}.{}]\ntitle = {}\ndate = \"{}\"\ntags = []\n\n",
+            year, title_value, title_value, date
+        );
+
+        fs::create_dir_all(file_path.parent().unwrap())?;
+        fs::write(&file_path, "")?;
+        fs::write(&metadata_path, entry + &metadata_source)?;
+
+        Ok(file_path)
+    }
+
+    /// Records the AT URI of the Bluesky post announcing the post at `path`
+    /// in its `index.toml` entry.
+    pub fn record_announcement(&mut self, path: &Path, uri: &str) -> Result<()> {
+        let name = |component: Option<&std::ffi::OsStr>| {
+            component
+                .and_then(|name| name.to_str())
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Unexpected post path {:?}", path))
+        };
+        let year = name(path.parent().and_then(Path::file_name))?;
+        let post_name = name(path.file_stem())?;
+
+        let (header_line, _) = self
+            .index_section(&year, &post_name)
+            .ok_or_else(|| anyhow!("No index.toml entry for {:?}", path))?;
+
+        let lines = self
+            .metadata_source
+            .split_inclusive('\n')
+            .collect::<Vec<_>>();
+        let updated = format!(
+            "{}bluesky_uri = {}\n{}",
+            lines[..header_line].concat(),
+            toml::Value::String(uri.to_string()),
+            lines[header_line..].concat()
+        );
+
+        let metadata_path = self.src.join("index.toml");
+        updated.parse::<Table>().with_context(|| {
+            format!("Recording {} would leave {:?} invalid", uri, metadata_path)
+        })?;
+        fs::write(&metadata_path, &updated)
+            .with_context(|| format!("Failed to write {:?}", metadata_path))?;
+        self.metadata_source = updated;
+        Ok(())
+    }
+
     pub fn posts(&self) -> Vec<&ParsedMarkdown> {
         let mut sorted_posts = self.parsed_posts.iter().collect::<Vec<_>>();
-        sorted_posts.sort_by(|a, b| b.front_matter.date.cmp(&a.front_matter.date));
+        sorted_posts.sort_by_key(|post| std::cmp::Reverse(post.front_matter.date));
         sorted_posts
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    const URI: &str = "at://did:plc:abc/app.bsky.feed.post/3l5announce";
+
+    /// Records `URI` for `post` in a collection backed by `index`, returning
+    /// the updated `index.toml`.
+    fn record(index: &str, post: &str) -> Result<String> {
+        let dir = test_dir();
+        let metadata_path = dir.path().join("index.toml");
+        fs::write(&metadata_path, index).unwrap();
+        let mut collection = PostsCollection {
+            src: dir.path().to_path_buf(),
+            metadata_source: index.to_string(),
+            ..PostsCollection::default()
+        };
+
+        collection.record_announcement(&dir.path().join(post), URI)?;
+        let written = fs::read_to_string(&metadata_path).unwrap();
+        assert_eq!(written, collection.metadata_source);
+        Ok(written)
+    }
+
+    #[test]
+    fn announcements_are_recorded_under_the_post_header() {
+        let index = "[2024.\"First post\"]\ntitle = \"First post\"\ndate = \"2024-01-01\"\n\n[2024.\"Second post\"]\ntitle = \"Second post\"\n";
+        assert_eq!(
+            record(index, "2024/Second post.md").unwrap(),
+            format!(
+                "[2024.\"First post\"]\ntitle = \"First post\"\ndate = \"2024-01-01\"\n\n[2024.\"Second post\"]\nbluesky_uri = \"{}\"\ntitle = \"Second post\"\n",
+                URI
+            )
+        );
+    }
+
+    #[test]
+    fn announcements_keep_the_sections_existing_keys_and_comments() {
+        let index = "# Posts\n[ 2023 . 'Old post' ]\n# Moved from the old site\ntitle = \"Old post\"\ntags = [\"meta\"]\n";
+        let recorded = record(index, "2023/Old post.md").unwrap();
+        assert_eq!(
+            recorded,
+            format!(
+                "# Posts\n[ 2023 . 'Old post' ]\nbluesky_uri = \"{}\"\n# Moved from the old site\ntitle = \"Old post\"\ntags = [\"meta\"]\n",
+                URI
+            )
+        );
+
+        let post = &recorded.parse::<Table>().unwrap()["2023"]["Old post"];
+        assert_eq!(post["bluesky_uri"].as_str(), Some(URI));
+        assert_eq!(post["tags"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn announcements_for_posts_without_an_entry_or_with_a_uri_fail() {
+        let index = "[2024.\"First post\"]\ntitle = \"First post\"\n";
+        let error = record(index, "2024/Missing.md").unwrap_err();
+        assert!(error.to_string().contains("No index.toml entry"));
+
+        // A second `bluesky_uri` would be a duplicate key
+        let index = "[2024.\"First post\"]\nbluesky_uri = \"at://old\"\n";
+        let error = record(index, "2024/First post.md").unwrap_err();
+        assert!(error.to_string().contains("would leave"), "{}", error);
+    }
+
+    #[test]
+    fn titles_that_would_escape_the_posts_directory_are_rejected() {
+        let src = Path::new("does-not-exist");
+        let date = PostDate::parse("2024-01-01").unwrap();
+        for title in ["../escape", "nested/post", "back\\slash", ".hidden", ".."] {
+            let error = PostsCollection::create_post(src, title, date)
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains("path separators"), "{}: {}", title, error);
+        }
+    }
+}
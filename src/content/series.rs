@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use crate::content::posts::PostsCollection;
+use crate::markdown::{slugify, ParsedMarkdown};
+
+/// A set of posts sharing the same `series` in their front matter.
+#[derive(Debug)]
+pub struct Series<'a> {
+    pub name: String,
+    pub slug: String,
+    /// In reading order, starting with part 1
+    pub posts: Vec<&'a ParsedMarkdown>,
+}
+
+impl Series<'_> {
+    /// Returns the zero-based index of `post` within the series.
+    pub fn position(&self, post: &ParsedMarkdown) -> Option<usize> {
+        self.posts.iter().position(|part| std::ptr::eq(*part, post))
+    }
+}
+
+impl PostsCollection {
+    /// Groups posts by series, sorted by series slug.
+    ///
+    /// Parts are ordered by `series_order` where given, with the rest
+    /// following by date, then title.
+    pub fn series(&self) -> Vec<Series<'_>> {
+        let mut series: BTreeMap<String, Series> = BTreeMap::new();
+
+        for post in self.posts() {
+            if let Some(name) = &post.front_matter.series {
+                let slug = slugify(name);
+                series
+                    .entry(slug.clone())
+                    .or_insert_with(|| Series {
+                        name: name.clone(),
+                        slug,
+                        posts: Vec::new(),
+                    })
+                    .posts
+                    .push(post);
+            }
+        }
+
+        for series in series.values_mut() {
+            series.posts.sort_by(|a, b| {
+                let a_order = a.front_matter.series_order.unwrap_or(u32::MAX);
+                let b_order = b.front_matter.series_order.unwrap_or(u32::MAX);
+                a_order
+                    .cmp(&b_order)
+                    .then_with(|| a.front_matter.date.cmp(&b.front_matter.date))
+                    .then_with(|| a.front_matter.title.cmp(&b.front_matter.title))
+            });
+        }
+
+        series.into_values().collect()
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use crate::content::posts::PostsCollection;
+use crate::markdown::{slugify, ParsedMarkdown};
+
+/// A tag and every post filed under it.
+#[derive(Debug)]
+pub struct Tag<'a> {
+    pub name: String,
+    pub slug: String,
+    /// Newest first
+    pub posts: Vec<&'a ParsedMarkdown>,
+}
+
+impl PostsCollection {
+    /// Groups posts by tag, sorted by tag slug.
+    ///
+    /// Tags are matched on their slug, so `WebDev` and `webdev` end up on the
+    /// same page under whichever spelling was seen first.
+    pub fn tags(&self) -> Vec<Tag<'_>> {
+        let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
+
+        for post in self.posts() {
+            for name in post.front_matter.tags.iter().flatten() {
+                let slug = slugify(name);
+                tags.entry(slug.clone())
+                    .or_insert_with(|| Tag {
+                        name: name.clone(),
+                        slug,
+                        posts: Vec::new(),
+                    })
+                    .posts
+                    .push(post);
+            }
+        }
+
+        tags.into_values().collect()
+    }
+}
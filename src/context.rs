@@ -1,19 +1,78 @@
+use crate::config::{SiteConfig, CONFIG_FILE};
 use crate::services::{
-    blue_sky::BlueSky, content::ContentSources, site_generator::SiteGenerator, Service,
+    blue_sky::BlueSky,
+    content::ContentSources,
+    registry::{self, ServiceRegistry},
+    site_generator::SiteGenerator,
 };
 use anyhow::Result;
+use reqwest::Url;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
-pub async fn init() -> Result<Arc<AppContext>> {
-    let blue_sky = Arc::new(RwLock::new(BlueSky::init().await?));
-    let content_sources = Arc::new(RwLock::new(ContentSources::init().await?));
-    let site_generator = Arc::new(RwLock::new(SiteGenerator::init().await?));
+/// Directory overrides, usually from the command line. Anything left unset
+/// falls back to `homebase.toml`, then to its default under the current
+/// directory.
+#[derive(Debug, Clone, Default)]
+pub struct ContextOptions {
+    /// Path to the config file [default: ./homebase.toml]
+    pub config: Option<PathBuf>,
+    pub content_dir: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub includes_dir: Option<PathBuf>,
+    pub templates_dir: Option<PathBuf>,
+    /// Where build state is kept between runs [default: ./target/homebase-cache]
+    pub cache_dir: Option<PathBuf>,
+    /// Skip network requests and build from cached data
+    pub offline: bool,
+}
+
+pub async fn init(options: ContextOptions) -> Result<Arc<AppContext>> {
+    let cwd = std::env::current_dir()?;
+
+    let config_path = options.config.unwrap_or_else(|| cwd.join(CONFIG_FILE));
+    let config = SiteConfig::load(&config_path)?;
+    let base_url = config.base_url();
+
+    let (blue_sky, content_sources, site_generator) = tokio::try_join!(
+        registry::init::<BlueSky>(),
+        registry::init::<ContentSources>(),
+        registry::init::<SiteGenerator>(),
+    )?;
+
+    {
+        let mut site_generator = site_generator.write().await;
+        site_generator.set_feed_options(config.feed.clone());
+        site_generator.set_date_format(config.site.date_format.clone());
+    }
 
+    let mut services = ServiceRegistry::default();
+    services.register(&content_sources)?;
+    services.register(&blue_sky)?;
+    services.register(&site_generator)?;
+
+    let directories = &config.directories;
     let cx = Arc::new(AppContext {
-        content_dir: std::env::current_dir()?.join("content"),
-        output_dir: std::env::current_dir()?.join("public"),
-        includes_dir: std::env::current_dir()?.join("src").join("includes"),
+        base_url,
+        content_dir: options
+            .content_dir
+            .unwrap_or_else(|| directories.content.clone()),
+        output_dir: options
+            .output_dir
+            .unwrap_or_else(|| directories.output.clone()),
+        includes_dir: options
+            .includes_dir
+            .unwrap_or_else(|| directories.includes.clone()),
+        templates_dir: options
+            .templates_dir
+            .unwrap_or_else(|| directories.templates.clone()),
+        cache_dir: options
+            .cache_dir
+            .unwrap_or_else(|| cwd.join("target").join("homebase-cache")),
+        offline: options.offline,
+        config,
+        services,
         blue_sky,
         content_sources,
         site_generator,
@@ -24,17 +83,23 @@ pub async fn init() -> Result<Arc<AppContext>> {
 
 #[derive(Clone)]
 pub struct AppContext {
+    base_url: Url,
+    config: SiteConfig,
     content_dir: PathBuf,
     output_dir: PathBuf,
     includes_dir: PathBuf,
+    templates_dir: PathBuf,
+    cache_dir: PathBuf,
+    offline: bool,
+    services: ServiceRegistry,
     blue_sky: Arc<RwLock<BlueSky>>,
     content_sources: Arc<RwLock<ContentSources>>,
     site_generator: Arc<RwLock<SiteGenerator>>,
 }
 
 impl AppContext {
-    pub async fn new() -> Result<Arc<Self>> {
-        init().await
+    pub async fn new(options: ContextOptions) -> Result<Arc<Self>> {
+        init(options).await
     }
 
     /// Returns the current working directory.
@@ -47,6 +112,40 @@ impl AppContext {
         self.includes_dir.clone()
     }
 
+    /// Returns the templates directory.
+    pub fn templates_dir(&self) -> PathBuf {
+        self.templates_dir.clone()
+    }
+
+    /// Returns the settings loaded from `homebase.toml`.
+    pub fn config(&self) -> &SiteConfig {
+        &self.config
+    }
+
+    /// Returns the public URL the site is deployed to.
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Resolves a path relative to the site root into an absolute URL.
+    pub fn absolute_url(&self, path: &str) -> Result<Url> {
+        let mut base_url = self.base_url.clone();
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Ok(base_url.join(path.trim_start_matches('/'))?)
+    }
+
+    /// Returns the directory build state is cached in between runs.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone()
+    }
+
+    /// Whether network requests should be skipped in favor of cached data.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
     /// Returns the content directory.
     pub fn content_dir(&self) -> PathBuf {
         self.content_dir.clone()
@@ -57,6 +156,11 @@ impl AppContext {
         self.output_dir.clone()
     }
 
+    /// Returns the registry that updates every service in dependency order.
+    pub fn services(&self) -> &ServiceRegistry {
+        &self.services
+    }
+
     pub fn blue_sky(&self) -> &Arc<RwLock<BlueSky>> {
         &self.blue_sky
     }
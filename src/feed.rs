@@ -0,0 +1,374 @@
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::context::AppContext;
+use crate::markdown::ParsedMarkdown;
+use crate::utils::html::escape_html;
+
+const EXCERPT_LENGTH: usize = 280;
+
+/// How much of each post ends up in the feed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    /// The full rendered post
+    #[default]
+    Full,
+    /// A short plain text summary, readers follow the link for the rest
+    Excerpt,
+}
+
+/// Set in the `[feed]` table of `homebase.toml`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedOptions {
+    pub content: FeedContent,
+    /// Maximum number of entries to include, newest first
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeedEntry {
+    /// A tag URI that stays the same as long as the post's date and slug do.
+    pub id: String,
+    pub title: String,
+    pub url: Url,
+    pub published: DateTime<Utc>,
+    pub updated: Option<DateTime<Utc>>,
+    pub summary: String,
+    pub content_html: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// A syndication feed for the posts collection, renderable as Atom, RSS 2.0
+/// and JSON Feed 1.1.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub author: String,
+    pub home_url: Url,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    pub const ATOM_PATH: &'static str = "feed.xml";
+    pub const RSS_PATH: &'static str = "rss.xml";
+    pub const JSON_PATH: &'static str = "feed.json";
+
+    /// Builds a feed from posts, which are expected to be sorted newest first.
+    /// `html` holds each post's content with shortcodes expanded, keyed by
+    /// path.
+    pub fn from_posts(
+        cx: &AppContext,
+        posts: &[&ParsedMarkdown],
+        html: &HashMap<PathBuf, String>,
+        options: &FeedOptions,
+    ) -> Result<Self> {
+        let limit = options.limit.unwrap_or(posts.len());
+        let entries = posts
+            .iter()
+            .take(limit)
+            .map(|post| FeedEntry::from_post(cx, post, html.get(&post.path), options))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            title: cx.config().site.title.clone(),
+            author: cx.config().site.author.clone(),
+            home_url: cx.absolute_url("")?,
+            entries,
+        })
+    }
+
+    /// The time of the newest entry, or now if the feed is empty.
+    fn updated(&self) -> DateTime<Utc> {
+        self.entries
+            .iter()
+            .map(|entry| entry.updated.unwrap_or(entry.published))
+            .max()
+            .unwrap_or_else(Utc::now)
+    }
+
+    fn feed_url(&self, path: &str) -> Result<Url> {
+        Ok(self.home_url.join(path)?)
+    }
+
+    pub fn to_atom(&self) -> Result<String> {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push_str(&format!("<title>{}</title>", escape_html(&self.title)));
+        xml.push_str(&format!("<id>{}</id>", escape_html(self.home_url.as_str())));
+        xml.push_str(&format!(
+            r#"<link rel="alternate" href="{}"/>"#,
+            escape_html(self.home_url.as_str())
+        ));
+        xml.push_str(&format!(
+            r#"<link rel="self" href="{}"/>"#,
+            escape_html(self.feed_url(Self::ATOM_PATH)?.as_str())
+        ));
+        xml.push_str(&format!("<updated>{}</updated>", rfc3339(&self.updated())));
+        xml.push_str(&format!(
+            "<author><name>{}</name></author>",
+            escape_html(&self.author)
+        ));
+
+        for entry in &self.entries {
+            xml.push_str("<entry>");
+            xml.push_str(&format!("<id>{}</id>", escape_html(&entry.id)));
+            xml.push_str(&format!("<title>{}</title>", escape_html(&entry.title)));
+            xml.push_str(&format!(
+                r#"<link rel="alternate" href="{}"/>"#,
+                escape_html(entry.url.as_str())
+            ));
+            xml.push_str(&format!(
+                "<published>{}</published>",
+                rfc3339(&entry.published)
+            ));
+            xml.push_str(&format!(
+                "<updated>{}</updated>",
+                rfc3339(&entry.updated.unwrap_or(entry.published))
+            ));
+            for tag in &entry.tags {
+                xml.push_str(&format!(r#"<category term="{}"/>"#, escape_html(tag)));
+            }
+            xml.push_str(&format!(
+                "<summary>{}</summary>",
+                escape_html(&entry.summary)
+            ));
+            if let Some(content_html) = &entry.content_html {
+                xml.push_str(&format!(
+                    r#"<content type="html">{}</content>"#,
+                    escape_html(content_html)
+                ));
+            }
+            xml.push_str("</entry>");
+        }
+
+        xml.push_str("</feed>\n");
+        Ok(xml)
+    }
+
+    pub fn to_rss(&self) -> Result<String> {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#);
+        xml.push_str("<channel>");
+        xml.push_str(&format!("<title>{}</title>", escape_html(&self.title)));
+        xml.push_str(&format!(
+            "<link>{}</link>",
+            escape_html(self.home_url.as_str())
+        ));
+        xml.push_str(&format!(
+            "<description>Posts by {}</description>",
+            escape_html(&self.author)
+        ));
+        xml.push_str(&format!(
+            r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+            escape_html(self.feed_url(Self::RSS_PATH)?.as_str())
+        ));
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>",
+            self.updated().to_rfc2822()
+        ));
+
+        for entry in &self.entries {
+            xml.push_str("<item>");
+            xml.push_str(&format!("<title>{}</title>", escape_html(&entry.title)));
+            xml.push_str(&format!("<link>{}</link>", escape_html(entry.url.as_str())));
+            xml.push_str(&format!(
+                r#"<guid isPermaLink="false">{}</guid>"#,
+                escape_html(&entry.id)
+            ));
+            xml.push_str(&format!(
+                "<pubDate>{}</pubDate>",
+                entry.published.to_rfc2822()
+            ));
+            for tag in &entry.tags {
+                xml.push_str(&format!("<category>{}</category>", escape_html(tag)));
+            }
+            let description = entry.content_html.as_ref().unwrap_or(&entry.summary);
+            xml.push_str(&format!(
+                "<description>{}</description>",
+                escape_html(description)
+            ));
+            xml.push_str("</item>");
+        }
+
+        xml.push_str("</channel></rss>\n");
+        Ok(xml)
+    }
+
+    pub fn to_json_feed(&self) -> Result<String> {
+        let items = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let mut item = json!({
+                    "id": entry.id,
+                    "url": entry.url.as_str(),
+                    "title": entry.title,
+                    "summary": entry.summary,
+                    "date_published": rfc3339(&entry.published),
+                    "tags": entry.tags,
+                });
+                if let Some(updated) = &entry.updated {
+                    item["date_modified"] = json!(rfc3339(updated));
+                }
+                match &entry.content_html {
+                    Some(content_html) => item["content_html"] = json!(content_html),
+                    None => item["content_text"] = json!(entry.summary),
+                }
+                item
+            })
+            .collect::<Vec<_>>();
+
+        let feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title,
+            "home_page_url": self.home_url.as_str(),
+            "feed_url": self.feed_url(Self::JSON_PATH)?.as_str(),
+            "authors": [{ "name": self.author }],
+            "items": items,
+        });
+
+        Ok(serde_json::to_string_pretty(&feed)?)
+    }
+}
+
+impl FeedEntry {
+    fn from_post(
+        cx: &AppContext,
+        post: &ParsedMarkdown,
+        html: Option<&String>,
+        options: &FeedOptions,
+    ) -> Result<Self> {
+        let front_matter = &post.front_matter;
+        let slug = post.slug();
+        let published = front_matter.date.to_utc();
+        let url = cx.absolute_url(&format!("{}.html", slug))?;
+
+        let id = format!(
+            "tag:{},{}:{}",
+            cx.base_url().host_str().unwrap_or("localhost"),
+            published.format("%Y-%m-%d"),
+            slug
+        );
+
+        let content_html = match options.content {
+            FeedContent::Full => Some(html.unwrap_or(&post.html_content).clone()),
+            FeedContent::Excerpt => None,
+        };
+
+        Ok(Self {
+            id,
+            title: front_matter.title.clone(),
+            url,
+            published,
+            updated: front_matter.updated.map(|updated| updated.to_utc()),
+            summary: post.excerpt(EXCERPT_LENGTH),
+            content_html,
+            tags: front_matter.tags.clone().unwrap_or_default(),
+        })
+    }
+}
+
+fn rfc3339(datetime: &DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ContextOptions;
+    use crate::markdown::FrontMatter;
+    use serde_json::Value;
+
+    fn post(title: &str, date: &str, body: &str) -> ParsedMarkdown {
+        let front_matter: FrontMatter = toml::from_str(&format!(
+            "title = {:?}\ndate = {:?}\ntags = [\"rust\"]\n",
+            title, date
+        ))
+        .unwrap();
+        ParsedMarkdown {
+            front_matter,
+            content: body.to_string(),
+            html_content: format!("<p>{}</p>\n", body),
+            toc: Vec::new(),
+            shortcodes: Vec::new(),
+            path: PathBuf::from(format!("content/{}.md", title)),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_feeds_link_absolute_urls_and_carry_expanded_content() {
+        let cx = AppContext::new(ContextOptions::default()).await.unwrap();
+        let newer = post("Newer", "2024-03-01", "Newer body.");
+        let older = post("Older", "2024-02-01", "Older body.");
+        let html = HashMap::from([(newer.path.clone(), "<p>Expanded</p>".to_string())]);
+
+        let feed =
+            Feed::from_posts(&cx, &[&newer, &older], &html, &FeedOptions::default()).unwrap();
+        let newer_url = cx.absolute_url("newer.html").unwrap();
+        let older_url = cx.absolute_url("older.html").unwrap();
+        assert_eq!(feed.entries.len(), 2);
+
+        let atom = feed.to_atom().unwrap();
+        assert!(atom.contains(&format!(
+            r#"<link rel="self" href="{}"/>"#,
+            cx.absolute_url(Feed::ATOM_PATH).unwrap()
+        )));
+        assert!(atom.contains(&format!(r#"<link rel="alternate" href="{}"/>"#, newer_url)));
+        assert!(atom.contains(r#"<content type="html">&lt;p&gt;Expanded&lt;/p&gt;</content>"#));
+        assert!(atom.contains(r#"<content type="html">&lt;p&gt;Older body.&lt;/p&gt;"#));
+
+        let rss = feed.to_rss().unwrap();
+        assert!(rss.contains(&format!(
+            r#"<atom:link href="{}" rel="self""#,
+            cx.absolute_url(Feed::RSS_PATH).unwrap()
+        )));
+        assert!(rss.contains(&format!("<link>{}</link>", older_url)));
+        assert!(rss.contains("<description>&lt;p&gt;Expanded&lt;/p&gt;</description>"));
+
+        let json: Value = serde_json::from_str(&feed.to_json_feed().unwrap()).unwrap();
+        assert_eq!(
+            json["feed_url"],
+            cx.absolute_url(Feed::JSON_PATH).unwrap().as_str()
+        );
+        assert_eq!(json["items"][0]["url"], newer_url.as_str());
+        assert_eq!(json["items"][0]["content_html"], "<p>Expanded</p>");
+        assert_eq!(json["items"][1]["url"], older_url.as_str());
+    }
+
+    #[tokio::test]
+    async fn excerpt_feeds_carry_summaries_up_to_the_limit() {
+        let cx = AppContext::new(ContextOptions::default()).await.unwrap();
+        let newer = post("Newer", "2024-03-01", "Newer body.");
+        let older = post("Older", "2024-02-01", "Older body.");
+        let options = FeedOptions {
+            content: FeedContent::Excerpt,
+            limit: Some(1),
+        };
+
+        let feed = Feed::from_posts(&cx, &[&newer, &older], &HashMap::new(), &options).unwrap();
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(feed.entries[0].content_html, None);
+
+        let atom = feed.to_atom().unwrap();
+        assert!(atom.contains("<summary>Newer body.</summary>"));
+        assert!(!atom.contains("<content"));
+        assert!(!atom.contains("Older"));
+
+        let rss = feed.to_rss().unwrap();
+        assert!(rss.contains("<description>Newer body.</description>"));
+
+        let json: Value = serde_json::from_str(&feed.to_json_feed().unwrap()).unwrap();
+        assert_eq!(json["items"].as_array().unwrap().len(), 1);
+        assert_eq!(json["items"][0]["content_text"], "Newer body.");
+        assert!(json["items"][0].get("content_html").is_none());
+    }
+}
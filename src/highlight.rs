@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Result};
+use std::{fs, ops::RangeInclusive, path::Path, sync::OnceLock};
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, line_tokens_to_classed_spans, ClassStyle},
+    parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::utils::html::escape_html;
+
+/// Prefix for every highlighting class, so they don't collide with the
+/// site's own styles.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The bundled syntect theme the stylesheet is generated from
+const THEME: &str = "InspiredGitHub";
+
+/// Written to the output directory next to `global.css`
+pub const STYLESHEET: &str = "highlight.css";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// How a fenced code block should be rendered, parsed from its info string,
+/// e.g. ```` ```rust {2,4-6} linenos ````.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CodeBlockOptions {
+    pub language: Option<String>,
+    pub line_numbers: bool,
+    /// One-based, inclusive
+    pub highlighted_lines: Vec<RangeInclusive<usize>>,
+}
+
+impl CodeBlockOptions {
+    pub fn parse(info: &str) -> Result<Self> {
+        let mut options = Self::default();
+        let mut rest = info.to_string();
+
+        if let Some(start) = info.find('{') {
+            let end = info[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow!("Unclosed line range in {:?}", info))?;
+            for range in info[start + 1..end].split(',').map(str::trim) {
+                if !range.is_empty() {
+                    options
+                        .highlighted_lines
+                        .push(parse_range(range).with_context(|| {
+                            format!("Invalid line range {:?} in {:?}", range, info)
+                        })?);
+                }
+            }
+            rest.replace_range(start..=end, " ");
+        }
+
+        for token in rest.split([' ', ',']).filter(|token| !token.is_empty()) {
+            match token {
+                "linenos" => options.line_numbers = true,
+                language if options.language.is_none() => {
+                    options.language = Some(language.to_string())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn is_highlighted(&self, line_number: usize) -> bool {
+        self.highlighted_lines
+            .iter()
+            .any(|range| range.contains(&line_number))
+    }
+}
+
+fn parse_range(range: &str) -> Result<RangeInclusive<usize>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (start.trim().parse()?, end.trim().parse()?);
+    if start == 0 || end < start {
+        return Err(anyhow!("expected a range like `3` or `3-5`"));
+    }
+    Ok(start..=end)
+}
+
+/// Renders a code block as `<pre>` with a class-based `<span>` per token and
+/// one `.line` span per line. Languages syntect doesn't know are rendered as
+/// plain text.
+pub fn highlight(code: &str, options: &CodeBlockOptions) -> Result<String> {
+    let syntax_set = syntax_set();
+    let syntax = options
+        .language
+        .as_deref()
+        .and_then(|language| syntax_set.find_syntax_by_token(language))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+
+    let lines = highlight_lines(code, syntax, syntax_set)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let line_number = index + 1;
+            let class = if options.is_highlighted(line_number) {
+                "line highlighted"
+            } else {
+                "line"
+            };
+            let number = if options.line_numbers {
+                format!(
+                    "<span class='line-number' aria-hidden='true'>{}</span>",
+                    line_number
+                )
+            } else {
+                String::new()
+            };
+            format!("<span class='{}'>{}{}</span>", class, number, line)
+        })
+        .collect::<Vec<_>>();
+
+    let language = options
+        .language
+        .as_ref()
+        .map(|language| format!(" data-lang='{}'", escape_html(language)))
+        .unwrap_or_default();
+
+    Ok(format!(
+        "<pre class='hl-code'{}><code>{}</code></pre>\n",
+        language,
+        lines.join("\n")
+    ))
+}
+
+/// Highlights each line on its own, closing the spans still open at the end
+/// of a line and reopening them on the next so lines can be wrapped.
+fn highlight_lines(
+    code: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<String>> {
+    let mut parse_state = ParseState::new(syntax);
+    let mut scope_stack = ScopeStack::new();
+    let mut lines = Vec::new();
+
+    for line in LinesWithEndings::from(code) {
+        let ops = parse_state.parse_line(line, syntax_set)?;
+
+        let mut html = scope_stack
+            .as_slice()
+            .iter()
+            .map(|scope| {
+                let classes = scope
+                    .build_string()
+                    .split('.')
+                    .map(|atom| format!("hl-{}", atom))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("<span class=\"{}\">", classes)
+            })
+            .collect::<String>();
+        let (spans, _) = line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut scope_stack)?;
+        html.push_str(&spans);
+        html.push_str(&"</span>".repeat(scope_stack.len()));
+
+        // Lines are joined with newlines once wrapped
+        if let Some(newline) = html.rfind('\n') {
+            html.remove(newline);
+        }
+        lines.push(html);
+    }
+
+    Ok(lines)
+}
+
+/// Writes the stylesheet for the highlighting classes to the output
+/// directory.
+pub fn write_stylesheet(output_dir: &Path) -> Result<()> {
+    let themes = ThemeSet::load_defaults();
+    let theme = themes
+        .themes
+        .get(THEME)
+        .ok_or_else(|| anyhow!("Missing syntax theme {:?}", THEME))?;
+    let css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+
+    let path = output_dir.join(STYLESHEET);
+    fs::write(&path, css).with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_info_strings() {
+        assert_eq!(
+            CodeBlockOptions::parse("").unwrap(),
+            CodeBlockOptions::default()
+        );
+        assert_eq!(
+            CodeBlockOptions::parse("rust {2, 4-6} linenos").unwrap(),
+            CodeBlockOptions {
+                language: Some("rust".to_string()),
+                line_numbers: true,
+                highlighted_lines: vec![2..=2, 4..=6],
+            }
+        );
+        assert!(CodeBlockOptions::parse("rust {6-4}").is_err());
+        assert!(CodeBlockOptions::parse("rust {2").is_err());
+    }
+
+    #[test]
+    fn highlights_by_language_with_one_span_per_line() {
+        let options = CodeBlockOptions::parse("rust {2} linenos").unwrap();
+        let html = highlight("/* a\nb */\nfn main() {}\n", &options).unwrap();
+
+        assert!(html.starts_with("<pre class='hl-code' data-lang='rust'><code>"));
+        let lines = html.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with(
+            "<span class='line highlighted'><span class='line-number' aria-hidden='true'>2</span>"
+        ));
+        // The comment spans both lines, so it's reopened on the second
+        assert!(lines[1].contains("hl-comment"));
+        assert!(lines[2].contains("hl-storage"));
+        for line in lines {
+            assert_eq!(
+                line.matches("<span").count(),
+                line.matches("</span>").count()
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_languages_are_escaped_plain_text() {
+        let options = CodeBlockOptions::parse("astro").unwrap();
+        let html = highlight("<p>{greeting}</p>\n", &options).unwrap();
+        assert!(html.contains("&lt;p&gt;{greeting}&lt;/p&gt;"));
+    }
+}
@@ -4,6 +4,6 @@ pub struct Includes {
 
 pub fn includes() -> Includes {
     Includes {
-        styles: vec!["global.css"],
+        styles: vec!["global.css", crate::highlight::STYLESHEET],
     }
 }
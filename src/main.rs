@@ -1,32 +1,45 @@
 #![allow(dead_code)]
 
+mod build;
+mod cache;
+mod cli;
+mod config;
 mod content;
 mod context;
+mod feed;
+mod highlight;
 mod includes;
 mod markdown;
+mod publish;
+mod server;
 mod services;
+mod shortcodes;
+mod templates;
 mod utils;
 
 use anyhow::Result;
+use clap::Parser;
+use cli::{Cli, Command, NewCommand};
+use content::posts::PostsCollection;
 use context::AppContext;
 use dotenv::dotenv;
 use log::{debug, error, info};
-use markdown::slugify;
-use services::site_generator::Layout;
-use services::UpdateableService;
+use markdown::PostDate;
+use services::{content::ContentSources, site_generator::SiteGenerator, Service};
 
 // todo!(): Stop blindly unwrapping
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
+    let cli = Cli::parse();
     info!("Starting application");
 
     dotenv().ok();
     info!("Loaded .env file");
 
     info!("Initializing AppContext");
-    let cx = match AppContext::new().await {
+    let cx = match AppContext::new(cli.context_options()).await {
         Ok(context) => context,
         Err(e) => {
             error!("Failed to initialize AppContext: {:?}", e);
@@ -38,62 +51,41 @@ async fn main() -> Result<()> {
     debug!("Content directory: {:?}", cx.content_dir());
     debug!("Output directory: {:?}", cx.output_dir());
     debug!("Includes directory: {:?}", cx.includes_dir());
-
-    if let Err(e) = cx.blue_sky().write().unwrap().update(&cx).await {
-        error!("Failed to update BlueSky: {:?}", e);
-        return Err(e);
-    }
-
-    let mut site_generator = cx.site_generator().write().unwrap();
-
-    // Generate index page
-    let content_sources = cx.content_sources().read().unwrap();
-    let posts = content_sources.posts_collection().posts();
-
-    info!("Found {} posts", posts.len());
-
-    let index_page = site_generator
-        .new_page(Layout::Index)
-        .title("hey ✌🏽")
-        .child("<div class='thin-column'>
-            <p>I'm nate butler, a designer & maker enabling people's creativity and ability share knowledge.</p>
-            <p>I want to help people create the things important to them—To empower them to create something themselves and feel the euphoria it brings. My goal is always to help the people around me level up, in their careers & lives.</p>
-            <p>I post about all types of things here. You will find a mix of work, top of mind, reflections, & process. Enjoy!</p>
-        </div>")
-        .child("<h2>Posts</h2>")
-        .children(posts.iter().map(|post| {
-            let slugified_title = slugify(&post.front_matter.title);
-            let slug = post.front_matter.slug.as_ref().unwrap_or(&slugified_title);
-            format!(
-                "<li><a href='{}.html'>{}</a> - {}</li>",
-                slug, post.front_matter.title, post.front_matter.date
-            )
-        }))
-        .child("<div class='thin-column'>
-            <p>This site is built with rust, html and css. It's source is <a href='https://github.com/iamnbutler/homebase'>fully open</a> and is hosted completely free. It's a work in progress, and will likely look and feel pretty rough as I figure out the apis and ways for us to compose, style, ship & deploy without spending anything!</p>
-        </div>")
-        .build();
-
-    site_generator.add_page(index_page);
-
-    for post in posts {
-        let slugified_title = slugify(&post.front_matter.title);
-        let slug = post.front_matter.slug.as_ref().unwrap_or(&slugified_title);
-        let post_page = site_generator
-            .new_page(Layout::Page)
-            .title(post.front_matter.title.clone())
-            .slug(slug.to_string())
-            .child(post.html_content.clone())
-            .build();
-        site_generator.add_page(post_page);
-    }
-
-    info!("Generating site");
-    if let Err(e) = site_generator.generate(&cx).await {
-        error!("Failed to generate site: {:?}", e);
-        return Err(e);
+    debug!("Templates directory: {:?}", cx.templates_dir());
+    debug!("Cache directory: {:?}", cx.cache_dir());
+
+    match cli.command.unwrap_or_default() {
+        Command::Build => {
+            cx.services().update_all(&cx).await?;
+            build::build(&cx).await?;
+            info!("Site generation complete");
+        }
+        Command::Serve { port } => {
+            cx.services().update_all(&cx).await?;
+            server::serve(cx, port).await?;
+        }
+        Command::New(NewCommand::Post { title }) => {
+            let date = PostDate::Date(chrono::Local::now().date_naive());
+            let path = PostsCollection::create_post(&cx.content_dir().join("posts"), &title, date)?;
+            println!("Created {}", path.display());
+        }
+        Command::Check => {
+            // Bluesky is left out so checks don't need the network
+            cx.services()
+                .update_only(&cx, &[ContentSources::NAME, SiteGenerator::NAME])
+                .await?;
+            build::check(&cx).await?;
+            println!("Content and templates are valid");
+        }
+        Command::Clean => build::clean(&cx)?,
+        Command::Publish { dry_run } => {
+            cx.services()
+                .update_only(&cx, &[ContentSources::NAME])
+                .await?;
+            publish::publish(&cx, dry_run).await?;
+        }
+        Command::Backfill => cx.blue_sky().write().await.backfill(&cx).await?,
     }
 
-    info!("Site generation complete");
     Ok(())
 }
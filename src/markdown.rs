@@ -1,41 +1,923 @@
-use anyhow::Result;
-use pulldown_cmark::{html, Options, Parser};
-use serde::Deserialize;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    iter::Peekable,
+    path::PathBuf,
+};
+use toml::{Table, Value};
 
-#[derive(Debug, Deserialize)]
+use crate::highlight::{self, CodeBlockOptions};
+use crate::shortcodes::{self, Shortcode};
+use crate::utils::html::escape_html;
+
+/// Front matter keys holding a `PostDate`, validated up front so bad values
+/// can be reported with their location.
+const DATE_KEYS: &[&str] = &["date", "updated"];
+
+/// Kinds of `> [!NOTE]` callout, also accepted by the `callout` shortcode.
+pub const CALLOUT_KINDS: &[&str] = &["note", "tip", "important", "warning", "caution"];
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct FrontMatter {
     pub title: String,
-    pub date: String,
+    pub date: PostDate,
+    /// When the post was last meaningfully changed
+    pub updated: Option<PostDate>,
     pub tags: Option<Vec<String>>,
     pub series: Option<String>,
+    /// Position within `series`, otherwise parts are ordered by date
+    pub series_order: Option<u32>,
     #[serde(default)]
     pub slug: Option<String>,
+    /// AT URI of the Bluesky post announcing this one, set by `publish`
+    pub bluesky_uri: Option<String>,
+    /// Show a table of contents built from the post's headings
+    #[serde(default)]
+    pub toc: bool,
+    /// Overrides the site's `[markdown]` extensions for this post
+    #[serde(default)]
+    pub markdown: MarkdownExtensions,
+}
+
+/// CommonMark extensions to enable, set site wide in the `[markdown]` table
+/// of `homebase.toml` and per post with a `markdown` table in front matter.
+/// Unset extensions fall back to the site, then to the defaults: tables and
+/// strikethrough on, everything else off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarkdownExtensions {
+    pub tables: Option<bool>,
+    pub strikethrough: Option<bool>,
+    /// Rendered as a numbered endnotes section with links back
+    pub footnotes: Option<bool>,
+    pub task_lists: Option<bool>,
+    /// Curly quotes, en and em dashes and ellipses
+    pub smart_punctuation: Option<bool>,
+    /// `{#id .class}` after a heading's text
+    pub heading_attributes: Option<bool>,
+    pub definition_lists: Option<bool>,
+}
+
+impl MarkdownExtensions {
+    /// These extensions with any set in `overrides` replacing them.
+    pub fn overridden_by(&self, overrides: &Self) -> Self {
+        Self {
+            tables: overrides.tables.or(self.tables),
+            strikethrough: overrides.strikethrough.or(self.strikethrough),
+            footnotes: overrides.footnotes.or(self.footnotes),
+            task_lists: overrides.task_lists.or(self.task_lists),
+            smart_punctuation: overrides.smart_punctuation.or(self.smart_punctuation),
+            heading_attributes: overrides.heading_attributes.or(self.heading_attributes),
+            definition_lists: overrides.definition_lists.or(self.definition_lists),
+        }
+    }
+
+    pub fn options(&self) -> Options {
+        let extensions = [
+            (self.tables, true, Options::ENABLE_TABLES),
+            (self.strikethrough, true, Options::ENABLE_STRIKETHROUGH),
+            (self.footnotes, false, Options::ENABLE_FOOTNOTES),
+            (self.task_lists, false, Options::ENABLE_TASKLISTS),
+            (
+                self.smart_punctuation,
+                false,
+                Options::ENABLE_SMART_PUNCTUATION,
+            ),
+            (
+                self.heading_attributes,
+                false,
+                Options::ENABLE_HEADING_ATTRIBUTES,
+            ),
+            (
+                self.definition_lists,
+                false,
+                Options::ENABLE_DEFINITION_LIST,
+            ),
+        ];
+
+        let mut options = Options::empty();
+        for (enabled, default, option) in extensions {
+            if enabled.unwrap_or(default) {
+                options.insert(option);
+            }
+        }
+        options
+    }
+}
+
+impl FrontMatter {
+    /// Merges a post's `index.toml` entry with the front matter embedded in the
+    /// post file.
+    ///
+    /// The file is the closer source of truth, so its keys take precedence over
+    /// the index. Keys present in both with different values are returned as
+    /// conflicts so the caller can report them.
+    pub fn merge(
+        index: Option<&MetaSource>,
+        embedded: Option<&MetaSource>,
+    ) -> Result<(FrontMatter, Vec<FrontMatterConflict>)> {
+        let mut merged = index.map(|index| index.table.clone()).unwrap_or_default();
+        let mut conflicts = Vec::new();
+
+        if let Some(embedded) = embedded {
+            for (key, value) in &embedded.table {
+                if let (Some(index), Some(existing)) = (index, merged.get(key)) {
+                    if existing != value {
+                        conflicts.push(FrontMatterConflict {
+                            key: key.clone(),
+                            index_value: existing.clone(),
+                            index_location: index.location(key),
+                            file_value: value.clone(),
+                            file_location: embedded.location(key),
+                        });
+                    }
+                }
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+
+        for key in DATE_KEYS {
+            let Some(value) = merged.get(*key) else {
+                continue;
+            };
+            let source = embedded
+                .filter(|embedded| embedded.table.contains_key(*key))
+                .or(index);
+            let location = source
+                .map(|source| source.location(key))
+                .unwrap_or_default();
+
+            match value.as_str() {
+                Some(date) => {
+                    PostDate::parse(date)
+                        .with_context(|| format!("{}: invalid `{}` {:?}", location, key, date))?;
+                }
+                None => {
+                    return Err(anyhow!(
+                        "{}: invalid `{}` {}, expected a string",
+                        location,
+                        key,
+                        value
+                    ))
+                }
+            }
+        }
+
+        let front_matter = Value::Table(merged)
+            .try_into()
+            .context("Failed to deserialize front matter")?;
+
+        Ok((front_matter, conflicts))
+    }
+}
+
+/// A key defined with different values in `index.toml` and a post's own front
+/// matter. The file value wins.
+#[derive(Debug, Clone)]
+pub struct FrontMatterConflict {
+    pub key: String,
+    pub index_value: Value,
+    pub index_location: String,
+    pub file_value: Value,
+    pub file_location: String,
+}
+
+/// A table of front matter keys along with the text it was parsed from, so
+/// problems can be reported against the right file and line.
+#[derive(Debug, Clone)]
+pub struct MetaSource {
+    pub table: Table,
+    pub path: PathBuf,
+    text: String,
+    /// Line number of the first line of `text` within `path`
+    first_line: usize,
+}
+
+impl MetaSource {
+    pub fn new(table: Table, path: PathBuf, text: &str, first_line: usize) -> Self {
+        Self {
+            table,
+            path,
+            text: text.to_string(),
+            first_line,
+        }
+    }
+
+    /// Returns `path:line` for the line defining `key`, or just the path if
+    /// the key can't be found.
+    pub fn location(&self, key: &str) -> String {
+        let line = self.text.lines().position(|line| {
+            line.trim_start()
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with(['=', ':']))
+        });
+
+        match line {
+            Some(line) => format!("{}:{}", self.path.display(), self.first_line + line),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// A post's publish or update date, either a plain date (`2024-02-26`) or a
+/// full RFC 3339 timestamp (`2024-02-26T09:30:00-05:00`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostDate {
+    Date(NaiveDate),
+    DateTime(DateTime<FixedOffset>),
+}
+
+impl PostDate {
+    pub const DEFAULT_FORMAT: &'static str = "%B %-d, %Y";
+
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+            return Ok(Self::DateTime(datetime));
+        }
+
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Self::Date)
+            .map_err(|_| {
+                anyhow!("expected a date like `2024-02-26` or an RFC 3339 timestamp like `2024-02-26T09:30:00-05:00`")
+            })
+    }
+
+    /// Plain dates are treated as midnight UTC.
+    pub fn to_utc(self) -> DateTime<Utc> {
+        match self {
+            Self::Date(date) => date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+            Self::DateTime(datetime) => datetime.with_timezone(&Utc),
+        }
+    }
+
+    /// Formats the date with a `strftime` style format string.
+    pub fn format(&self, format: &str) -> String {
+        match self {
+            Self::Date(date) => date.format(format).to_string(),
+            Self::DateTime(datetime) => datetime.format(format).to_string(),
+        }
+    }
+}
+
+impl Ord for PostDate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.to_utc().cmp(&other.to_utc())
+    }
+}
+
+impl PartialOrd for PostDate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PostDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            Self::DateTime(datetime) => write!(f, "{}", datetime.to_rfc3339()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PostDate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        PostDate::parse(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for PostDate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterFormat {
+    /// Delimited by `---`
+    Yaml,
+    /// Delimited by `+++`
+    Toml,
+}
+
+impl FrontMatterFormat {
+    fn from_delimiter(line: &str) -> Option<Self> {
+        match line.trim_end() {
+            "---" => Some(Self::Yaml),
+            "+++" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    fn delimiter(&self) -> &'static str {
+        match self {
+            Self::Yaml => "---",
+            Self::Toml => "+++",
+        }
+    }
+
+    /// Parses front matter starting on `first_line` of its file, so errors
+    /// point at the right line.
+    fn parse(&self, raw: &str, first_line: usize) -> Result<Table> {
+        if raw.trim().is_empty() {
+            return Ok(Table::new());
+        }
+
+        // Both parsers report lines from the start of their input, so pad it
+        // out to where the front matter starts
+        let raw = format!("{}{}", "\n".repeat(first_line.saturating_sub(1)), raw);
+        let table = match self {
+            Self::Yaml => {
+                let mut value = serde_yaml::from_str::<serde_yaml::Value>(&raw)?;
+                // A key without a value, like `series:`, is left unset
+                drop_yaml_nulls(&mut value);
+                Table::deserialize(value)?
+            }
+            Self::Toml => raw.parse::<Table>()?,
+        };
+
+        // TOML dates are their own type, but `FrontMatter` expects strings
+        Ok(table
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::Datetime(datetime) => (key, Value::String(datetime.to_string())),
+                value => (key, value),
+            })
+            .collect())
+    }
 }
 
 #[derive(Debug)]
 pub struct ParsedMarkdown {
     pub front_matter: FrontMatter,
+    /// The body, with shortcodes replaced by placeholders
     pub content: String,
     pub html_content: String,
+    /// The post's headings, nested by level
+    pub toc: Vec<TocEntry>,
+    /// Expanded into `html_content` when pages are assembled
+    pub shortcodes: Vec<Shortcode>,
+    /// The file the post was read from
+    pub path: PathBuf,
+}
+
+impl ParsedMarkdown {
+    /// Returns the post's slug, falling back to its slugified title.
+    pub fn slug(&self) -> String {
+        self.front_matter
+            .slug
+            .clone()
+            .unwrap_or_else(|| slugify(&self.front_matter.title))
+    }
+
+    /// Returns the first paragraph of the post as plain text, cut at a word
+    /// boundary if it is longer than `max_chars`. Shortcodes and their
+    /// bodies are skipped.
+    pub fn excerpt(&self, max_chars: usize) -> String {
+        let mut excerpt = String::new();
+        let mut in_paragraph = false;
+
+        let content = shortcodes::strip(&self.content, &self.shortcodes);
+        for event in Parser::new(&content) {
+            match event {
+                Event::Start(Tag::Paragraph) => in_paragraph = true,
+                Event::End(TagEnd::Paragraph) => {
+                    in_paragraph = false;
+                    if !excerpt.trim().is_empty() {
+                        break;
+                    }
+                }
+                Event::Text(text) | Event::Code(text) if in_paragraph => excerpt.push_str(&text),
+                Event::SoftBreak | Event::HardBreak if in_paragraph => excerpt.push(' '),
+                _ => {}
+            }
+        }
+
+        let excerpt = excerpt.trim();
+        if excerpt.chars().count() <= max_chars {
+            return excerpt.to_string();
+        }
+
+        let truncated: String = excerpt.chars().take(max_chars).collect();
+        let truncated = match truncated.rfind(char::is_whitespace) {
+            Some(index) => &truncated[..index],
+            None => &truncated,
+        };
+        format!("{}…", truncated.trim_end())
+    }
+}
+
+/// A heading in a post's table of contents.
+#[derive(Debug, Clone, Serialize)]
+pub struct TocEntry {
+    pub level: u8,
+    /// The heading's `id`, unique within the post
+    pub id: String,
+    /// Plain text, without any inline formatting
+    pub title: String,
+    /// Headings of a deeper level that follow this one
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// Nests headings under the closest preceding heading of a shallower
+    /// level.
+    fn nest(headings: &mut Peekable<impl Iterator<Item = TocEntry>>, level: u8) -> Vec<Self> {
+        let mut entries = Vec::new();
+        while let Some(mut entry) = headings.next_if(|heading| heading.level > level) {
+            entry.children = Self::nest(headings, entry.level);
+            entries.push(entry);
+        }
+        entries
+    }
+}
+
+/// A post body rendered to HTML.
+#[derive(Debug)]
+pub struct RenderedMarkdown {
+    /// Shortcodes are left as placeholders, see `shortcodes::expand`
+    pub html: String,
+    /// The markdown that was rendered, with the same placeholders
+    pub markdown: String,
+    pub toc: Vec<TocEntry>,
+    pub shortcodes: Vec<Shortcode>,
 }
 
 pub struct Markdown;
 
+/// Footnote numbering, by order of first reference.
+#[derive(Default)]
+struct Footnotes<'a> {
+    order: Vec<CowStr<'a>>,
+    /// How many times each footnote in `order` has been referenced
+    references: Vec<usize>,
+}
+
+impl<'a> Footnotes<'a> {
+    fn reference_id(number: usize, reference: usize) -> String {
+        match reference {
+            1 => format!("fnref-{}", number),
+            reference => format!("fnref-{}-{}", number, reference),
+        }
+    }
+
+    /// Replaces footnote references with superscript links to the endnotes.
+    fn link_references(&mut self, events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        events
+            .into_iter()
+            .map(|event| {
+                let Event::FootnoteReference(name) = event else {
+                    return event;
+                };
+                let index = match self.order.iter().position(|other| *other == name) {
+                    Some(index) => index,
+                    None => {
+                        self.order.push(name);
+                        self.references.push(0);
+                        self.order.len() - 1
+                    }
+                };
+                self.references[index] += 1;
+
+                let number = index + 1;
+                Event::InlineHtml(
+                    format!(
+                        "<sup class='footnote-ref' id='{}'><a href='#fn-{}'>{}</a></sup>",
+                        Self::reference_id(number, self.references[index]),
+                        number,
+                        number
+                    )
+                    .into(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// The `[!KIND]` opening a callout blockquote. A `-` or `+` straight after
+/// it makes the callout collapsible, collapsed or expanded, and the rest of
+/// the line replaces the default title.
+#[derive(Debug, PartialEq)]
+struct CalloutMarker {
+    kind: &'static str,
+    /// Whether a collapsible callout starts expanded
+    open: Option<bool>,
+    title: String,
+}
+
+impl CalloutMarker {
+    fn parse(line: &str) -> Option<Self> {
+        let (kind, rest) = line.trim_start().strip_prefix("[!")?.split_once(']')?;
+        let kind = CALLOUT_KINDS
+            .iter()
+            .copied()
+            .find(|known| known.eq_ignore_ascii_case(kind))?;
+        let (open, rest) = match rest.chars().next() {
+            Some('-') => (Some(false), &rest[1..]),
+            Some('+') => (Some(true), &rest[1..]),
+            _ => (None, rest),
+        };
+        Some(Self {
+            kind,
+            open,
+            title: rest.trim_start().to_string(),
+        })
+    }
+
+    /// The kind, capitalized
+    fn default_title(&self) -> String {
+        let mut chars = self.kind.chars();
+        chars
+            .next()
+            .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+            .unwrap_or_default()
+    }
+}
+
 impl Markdown {
-    pub fn parse(content: &str) -> Result<String> {
-        // Set up options for the parser
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_STRIKETHROUGH);
-        options.insert(Options::ENABLE_TABLES);
+    /// The line front matter keys start on, just after the opening delimiter.
+    pub const FRONT_MATTER_FIRST_LINE: usize = 2;
+
+    /// Splits YAML (`---`) or TOML (`+++`) front matter off the top of a
+    /// document, returning the parsed front matter and its raw text, if any,
+    /// and the remaining markdown body.
+    pub fn extract_front_matter(content: &str) -> Result<(Option<(Table, &str)>, &str)> {
+        let content = content.trim_start_matches('\u{feff}');
+        let mut lines = content.split_inclusive('\n');
+
+        let Some(opening) = lines.next() else {
+            return Ok((None, content));
+        };
+        let Some(format) = FrontMatterFormat::from_delimiter(opening) else {
+            return Ok((None, content));
+        };
+
+        let mut offset = opening.len();
+        for line in lines {
+            if line.trim_end() == format.delimiter() {
+                let raw = &content[opening.len()..offset];
+                let body = &content[offset + line.len()..];
+                let front_matter = format
+                    .parse(raw, Self::FRONT_MATTER_FIRST_LINE)
+                    .with_context(|| format!("Invalid {:?} front matter", format))?;
+                return Ok((Some((front_matter, raw)), body));
+            }
+            offset += line.len();
+        }
+
+        Err(anyhow!(
+            "Front matter is missing its closing `{}`",
+            format.delimiter()
+        ))
+    }
+
+    /// Renders a post body starting on `first_line` of its file, which
+    /// shortcode errors are reported against.
+    pub fn parse(
+        content: &str,
+        first_line: usize,
+        extensions: &MarkdownExtensions,
+    ) -> Result<RenderedMarkdown> {
+        let (content, shortcodes) = shortcodes::extract(content, first_line)?;
 
         // Parse the markdown
-        let parser = Parser::new_ext(content, options);
+        let parser = Parser::new_ext(&content, extensions.options());
+        let events = Self::highlight_code_blocks(parser)?;
+        let events = Self::render_callouts(events);
+        let (events, headings) = Self::anchor_headings(events);
+        let events = Self::collect_endnotes(events);
 
         // Write to String buffer
         let mut html_output = String::new();
-        html::push_html(&mut html_output, parser);
+        html::push_html(&mut html_output, events.into_iter());
+
+        Ok(RenderedMarkdown {
+            html: html_output,
+            markdown: content,
+            toc: TocEntry::nest(&mut headings.into_iter().peekable(), 0),
+            shortcodes,
+        })
+    }
+
+    /// Gives every heading an id slugified from its text, suffixed with a
+    /// number if an earlier heading already has it, and a permalink anchor.
+    /// Returns the headings in document order alongside the events.
+    fn anchor_headings<'a>(events: Vec<Event<'a>>) -> (Vec<Event<'a>>, Vec<TocEntry>) {
+        let mut output = Vec::with_capacity(events.len());
+        let mut headings = Vec::new();
+        let mut ids = HashSet::new();
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            let (level, explicit_id, classes, attrs) = match event {
+                Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                }) => (level, id, classes, attrs),
+                event => {
+                    output.push(event);
+                    continue;
+                }
+            };
+
+            let mut inner = Vec::new();
+            let mut title = String::new();
+            for event in events.by_ref() {
+                match &event {
+                    Event::End(TagEnd::Heading(_)) => break,
+                    Event::Text(text) | Event::Code(text) => title.push_str(text),
+                    _ => {}
+                }
+                inner.push(event);
+            }
+
+            // Heading attributes can set the id explicitly
+            let slug = match explicit_id {
+                Some(id) => id.to_string(),
+                None => slugify(&title),
+            };
+            let slug = if slug.is_empty() {
+                "section".to_string()
+            } else {
+                slug
+            };
+            let mut id = slug.clone();
+            let mut duplicates = 0;
+            while ids.contains(&id) {
+                duplicates += 1;
+                id = format!("{}-{}", slug, duplicates);
+            }
+            ids.insert(id.clone());
+
+            output.push(Event::Start(Tag::Heading {
+                level,
+                id: Some(id.clone().into()),
+                classes,
+                attrs,
+            }));
+            output.extend(inner);
+            output.push(Event::InlineHtml(
+                format!(
+                    "<a class='anchor' href='#{}' aria-label='Link to this section'>#</a>",
+                    escape_html(&id)
+                )
+                .into(),
+            ));
+            output.push(Event::End(TagEnd::Heading(level)));
+
+            headings.push(TocEntry {
+                level: level as u8,
+                id,
+                title: title.trim().to_string(),
+                children: Vec::new(),
+            });
+        }
+
+        (output, headings)
+    }
+
+    /// Moves footnote definitions into a numbered endnotes section at the
+    /// end, ordered by first reference, with links back to each reference.
+    /// Definitions that are never referenced are left out.
+    fn collect_endnotes<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        let mut body = Vec::with_capacity(events.len());
+        let mut definitions = HashMap::new();
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            let Event::Start(Tag::FootnoteDefinition(name)) = event else {
+                body.push(event);
+                continue;
+            };
+            let definition = events
+                .by_ref()
+                .take_while(|event| !matches!(event, Event::End(TagEnd::FootnoteDefinition)))
+                .collect::<Vec<_>>();
+            definitions.entry(name).or_insert(definition);
+        }
+
+        if definitions.is_empty() {
+            return body;
+        }
+
+        let mut footnotes = Footnotes::default();
+        let mut output = footnotes.link_references(body);
+
+        // Footnotes can reference each other, adding to the order as they go
+        let mut notes = Vec::new();
+        while let Some(name) = footnotes.order.get(notes.len()).cloned() {
+            let definition = definitions.remove(&name).unwrap_or_default();
+            notes.push(footnotes.link_references(definition));
+        }
+        if notes.is_empty() {
+            return output;
+        }
+
+        output.push(Event::Html(
+            "<section class='footnotes' role='doc-endnotes'>\n<ol>\n".into(),
+        ));
+        for (index, mut note) in notes.into_iter().enumerate() {
+            let number = index + 1;
+            let backlinks = (1..=footnotes.references[number - 1])
+                .map(|reference| {
+                    format!(
+                        "<a class='footnote-backref' href='#{}' aria-label='Back to reference {}'>↩</a>",
+                        Footnotes::reference_id(number, reference),
+                        number
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            output.push(Event::Html(format!("<li id='fn-{}'>\n", number).into()));
+            // Keep the links on the last line of a paragraph if there is one
+            let backlinks = Event::InlineHtml(format!(" {}", backlinks).into());
+            match note.pop() {
+                Some(Event::End(TagEnd::Paragraph)) => {
+                    note.push(backlinks);
+                    note.push(Event::End(TagEnd::Paragraph));
+                }
+                last => {
+                    note.extend(last);
+                    note.push(backlinks);
+                }
+            }
+            output.extend(note);
+            output.push(Event::Html("</li>\n".into()));
+        }
+        output.push(Event::Html("</ol>\n</section>\n".into()));
+
+        output
+    }
+
+    /// Turns blockquotes whose first line starts with a `[!NOTE]` style
+    /// marker into callouts. Collapsible ones become `<details>`, everything
+    /// else an `<aside>`.
+    fn render_callouts<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        let mut output = Vec::with_capacity(events.len());
+        // The closing tag for each open blockquote that became a callout
+        let mut blockquotes: Vec<Option<&'static str>> = Vec::new();
+        let mut events = events.into_iter().peekable();
+
+        while let Some(event) = events.next() {
+            let kind = match event {
+                Event::Start(Tag::BlockQuote(kind)) => kind,
+                Event::End(TagEnd::BlockQuote(kind)) => {
+                    match blockquotes.pop().flatten() {
+                        Some(close) => output.push(Event::Html(close.into())),
+                        None => output.push(Event::End(TagEnd::BlockQuote(kind))),
+                    }
+                    continue;
+                }
+                event => {
+                    output.push(event);
+                    continue;
+                }
+            };
+
+            if !matches!(events.peek(), Some(Event::Start(Tag::Paragraph))) {
+                blockquotes.push(None);
+                output.push(Event::Start(Tag::BlockQuote(kind)));
+                continue;
+            }
+            let paragraph = events.next();
+
+            // The first line of the paragraph, up to and including its break
+            let mut line = Vec::new();
+            for event in events.by_ref() {
+                let end = matches!(
+                    event,
+                    Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph)
+                );
+                line.push(event);
+                if end {
+                    break;
+                }
+            }
+
+            // Brackets can split the marker across several text events
+            let text = line
+                .iter()
+                .map_while(|event| match event {
+                    Event::Text(text) => Some(text.as_ref()),
+                    _ => None,
+                })
+                .collect::<String>();
+            let Some(marker) = CalloutMarker::parse(&text) else {
+                blockquotes.push(None);
+                output.push(Event::Start(Tag::BlockQuote(kind)));
+                output.extend(paragraph);
+                output.extend(line);
+                continue;
+            };
+
+            let leading_text = line
+                .iter()
+                .take_while(|event| matches!(event, Event::Text(_)))
+                .count();
+            let end = line.pop();
+            let mut title = Vec::new();
+            if !marker.title.is_empty() {
+                title.push(Event::Text(marker.title.clone().into()));
+            }
+            title.extend(line.into_iter().skip(leading_text));
+            if title.is_empty() {
+                title.push(Event::Text(marker.default_title().into()));
+            }
+
+            let (open, close) = match marker.open {
+                Some(expanded) => (
+                    format!(
+                        "<details class='callout {}'{}>\n<summary class='callout-title'>",
+                        marker.kind,
+                        if expanded { " open" } else { "" }
+                    ),
+                    "</summary>\n",
+                ),
+                None => (
+                    format!(
+                        "<aside class='callout {}'>\n<p class='callout-title'>",
+                        marker.kind
+                    ),
+                    "</p>\n",
+                ),
+            };
+            output.push(Event::Html(open.into()));
+            output.extend(title);
+            output.push(Event::Html(close.into()));
+
+            // The rest of the first paragraph, if it continues past the marker
+            if !matches!(end, Some(Event::End(TagEnd::Paragraph))) {
+                output.push(Event::Start(Tag::Paragraph));
+            }
+
+            blockquotes.push(Some(match marker.open {
+                Some(_) => "</details>\n",
+                None => "</aside>\n",
+            }));
+        }
+
+        output
+    }
+
+    /// Replaces each fenced code block with its highlighted HTML.
+    fn highlight_code_blocks<'a>(
+        events: impl Iterator<Item = Event<'a>>,
+    ) -> Result<Vec<Event<'a>>> {
+        let mut output = Vec::new();
+        let mut code_block: Option<(CodeBlockOptions, String)> = None;
+
+        for event in events {
+            if let Some((options, code)) = &mut code_block {
+                match event {
+                    Event::Text(text) => code.push_str(&text),
+                    Event::End(TagEnd::CodeBlock) => {
+                        output.push(Event::Html(highlight::highlight(code, options)?.into()));
+                        code_block = None;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                    code_block = Some((CodeBlockOptions::parse(&info)?, String::new()));
+                }
+                event => output.push(event),
+            }
+        }
+
+        Ok(output)
+    }
+}
 
-        Ok(html_output)
+/// Removes nulls from YAML mappings and sequences, since TOML has no null.
+fn drop_yaml_nulls(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            mapping.retain(|_, value| !value.is_null());
+            mapping.values_mut().for_each(drop_yaml_nulls);
+        }
+        serde_yaml::Value::Sequence(sequence) => {
+            sequence.retain(|value| !value.is_null());
+            sequence.iter_mut().for_each(drop_yaml_nulls);
+        }
+        _ => {}
     }
 }
 
@@ -58,3 +940,292 @@ where
     let title: String = String::deserialize(deserializer)?;
     Ok(slugify(&title))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta_source(path: &str, text: &str, first_line: usize) -> MetaSource {
+        MetaSource::new(text.parse().unwrap(), PathBuf::from(path), text, first_line)
+    }
+
+    #[test]
+    fn file_front_matter_wins_over_the_index() {
+        let index = meta_source(
+            "content/index.toml",
+            "title = \"Index title\"\ndate = \"2024-02-26\"\ntags = [\"rust\"]\n",
+            4,
+        );
+        let file = meta_source(
+            "content/2024/post.md",
+            "title = \"File title\"\ntags = [\"rust\"]\nseries = \"Notes\"\n",
+            Markdown::FRONT_MATTER_FIRST_LINE,
+        );
+        let (front_matter, conflicts) = FrontMatter::merge(Some(&index), Some(&file)).unwrap();
+
+        assert_eq!(front_matter.title, "File title");
+        assert_eq!(front_matter.date, PostDate::parse("2024-02-26").unwrap());
+        assert_eq!(front_matter.series.as_deref(), Some("Notes"));
+
+        // Equal values aren't conflicts
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert_eq!(conflict.key, "title");
+        assert_eq!(conflict.index_value.as_str(), Some("Index title"));
+        assert_eq!(conflict.index_location, "content/index.toml:4");
+        assert_eq!(conflict.file_value.as_str(), Some("File title"));
+        assert_eq!(conflict.file_location, "content/2024/post.md:2");
+    }
+
+    #[test]
+    fn parses_dates_and_timestamps() {
+        let date = PostDate::parse(" 2024-02-26 ").unwrap();
+        assert_eq!(
+            date,
+            PostDate::Date(NaiveDate::from_ymd_opt(2024, 2, 26).unwrap())
+        );
+        assert_eq!(date.to_string(), "2024-02-26");
+        assert_eq!(date.format(PostDate::DEFAULT_FORMAT), "February 26, 2024");
+
+        let datetime = PostDate::parse("2024-02-26T09:30:00-05:00").unwrap();
+        assert_eq!(datetime.to_string(), "2024-02-26T09:30:00-05:00");
+        assert_eq!(datetime.to_utc().to_rfc3339(), "2024-02-26T14:30:00+00:00");
+        assert!(PostDate::parse("2024-02-26T14:30:00Z").is_ok());
+
+        // Plain dates are midnight UTC, so they sort before later timestamps
+        assert!(date < datetime);
+
+        for invalid in ["26/02/2024", "2024-02-30", "2024-02-26 09:30", ""] {
+            assert!(PostDate::parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn toml_front_matter_dates_can_be_unquoted() {
+        let (front_matter, body) = Markdown::extract_front_matter(
+            "+++\ntitle = \"Dates\"\ndate = 2024-02-26\n+++\nBody\n",
+        )
+        .unwrap();
+        let (table, raw) = front_matter.unwrap();
+        let source = MetaSource::new(
+            table,
+            PathBuf::from("post.md"),
+            raw,
+            Markdown::FRONT_MATTER_FIRST_LINE,
+        );
+        let (front_matter, _) = FrontMatter::merge(None, Some(&source)).unwrap();
+
+        assert_eq!(front_matter.date, PostDate::parse("2024-02-26").unwrap());
+        assert_eq!(body, "Body\n");
+    }
+
+    #[test]
+    fn yaml_keys_without_values_are_unset() {
+        let (front_matter, _) = Markdown::extract_front_matter(
+            "---\ntitle: Post\ndate: 2024-02-26\nseries:\ntags:\n  - rust\n  -\n---\nBody\n",
+        )
+        .unwrap();
+        let (table, raw) = front_matter.unwrap();
+        assert!(!table.contains_key("series"));
+
+        let source = MetaSource::new(
+            table,
+            PathBuf::from("post.md"),
+            raw,
+            Markdown::FRONT_MATTER_FIRST_LINE,
+        );
+        let (front_matter, _) = FrontMatter::merge(None, Some(&source)).unwrap();
+        assert_eq!(front_matter.series, None);
+        assert_eq!(front_matter.tags, Some(vec!["rust".to_string()]));
+    }
+
+    #[test]
+    fn front_matter_syntax_errors_point_at_the_line_in_the_file() {
+        let error = |content| format!("{:#}", Markdown::extract_front_matter(content).unwrap_err());
+
+        let yaml = error("---\ntitle: Post\ndate: [2024-02-26\n---\n");
+        assert!(yaml.starts_with("Invalid Yaml front matter"), "{}", yaml);
+        assert!(yaml.contains("line 4"), "{}", yaml);
+
+        let toml = error("+++\ntitle = \"Post\"\ndate =\n+++\n");
+        assert!(toml.starts_with("Invalid Toml front matter"), "{}", toml);
+        assert!(toml.contains("line 3"), "{}", toml);
+    }
+
+    #[test]
+    fn bad_dates_are_reported_with_their_location() {
+        let index = meta_source(
+            "content/index.toml",
+            "title = \"Post\"\ndate = \"2024-02-26\"\nupdated = 3\n",
+            10,
+        );
+        let file = meta_source(
+            "content/2024/post.md",
+            "title = \"Post\"\ndate = \"Feb 26\"\n",
+            Markdown::FRONT_MATTER_FIRST_LINE,
+        );
+
+        let error = FrontMatter::merge(Some(&index), Some(&file)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "content/2024/post.md:3: invalid `date` \"Feb 26\""
+        );
+
+        let error = FrontMatter::merge(Some(&index), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "content/index.toml:12: invalid `updated` 3, expected a string"
+        );
+    }
+
+    #[test]
+    fn merges_metadata_from_either_source_alone() {
+        let index = meta_source(
+            "content/index.toml",
+            "title = \"Only indexed\"\ndate = \"2024-02-26\"\n",
+            1,
+        );
+        let (front_matter, conflicts) = FrontMatter::merge(Some(&index), None).unwrap();
+        assert_eq!(front_matter.title, "Only indexed");
+        assert!(conflicts.is_empty());
+
+        let file = meta_source(
+            "content/2024/post.md",
+            "title = \"Only in the file\"\ndate = \"2024-02-27\"\n",
+            Markdown::FRONT_MATTER_FIRST_LINE,
+        );
+        let (front_matter, conflicts) = FrontMatter::merge(None, Some(&file)).unwrap();
+        assert_eq!(front_matter.title, "Only in the file");
+        assert!(conflicts.is_empty());
+
+        let error = FrontMatter::merge(None, None).unwrap_err();
+        assert!(format!("{:#}", error).contains("missing field `title`"));
+    }
+
+    #[test]
+    fn headings_get_unique_ids_and_nest_into_a_toc() {
+        let rendered = Markdown::parse(
+            "## Setup\n\n### `cargo` install\n\n## Setup\n\n#### Deep\n\n## 🎉\n",
+            1,
+            &MarkdownExtensions::default(),
+        )
+        .unwrap();
+
+        assert!(rendered.html.contains(
+            "<h2 id=\"setup\">Setup<a class='anchor' href='#setup' aria-label='Link to this section'>#</a></h2>"
+        ));
+        assert!(rendered.html.contains("<h2 id=\"setup-1\">"));
+        assert!(rendered.html.contains("<h2 id=\"section\">"));
+
+        let ids = |entries: &[TocEntry]| {
+            entries
+                .iter()
+                .map(|entry| entry.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&rendered.toc), ["setup", "setup-1", "section"]);
+        assert_eq!(ids(&rendered.toc[0].children), ["cargo-install"]);
+        assert_eq!(rendered.toc[0].children[0].title, "cargo install");
+        assert_eq!(ids(&rendered.toc[1].children), ["deep"]);
+    }
+
+    #[test]
+    fn footnotes_become_endnotes_with_backlinks() {
+        let extensions = MarkdownExtensions {
+            footnotes: Some(true),
+            ..MarkdownExtensions::default()
+        };
+        let rendered = Markdown::parse(
+            "One[^b] and two[^a], one again[^b].\n\n[^a]: Second.\n\n[^b]: First.\n\n[^unused]: Never.\n",
+            1,
+            &extensions,
+        )
+        .unwrap();
+        let html = rendered.html;
+
+        assert!(
+            html.contains("One<sup class='footnote-ref' id='fnref-1'><a href='#fn-1'>1</a></sup>")
+        );
+        assert!(
+            html.contains("<sup class='footnote-ref' id='fnref-1-2'><a href='#fn-1'>1</a></sup>")
+        );
+        assert!(html.contains("<li id='fn-1'>\n<p>First. <a class='footnote-backref' href='#fnref-1' aria-label='Back to reference 1'>↩</a> <a class='footnote-backref' href='#fnref-1-2'"));
+        assert!(
+            html.contains("<li id='fn-2'>\n<p>Second. <a class='footnote-backref' href='#fnref-2'")
+        );
+        assert!(!html.contains("Never"));
+        assert!(html.find("<section class='footnotes'").unwrap() > html.find("one again").unwrap());
+    }
+
+    #[test]
+    fn excerpts_skip_shortcodes_and_their_bodies() {
+        let excerpt = |body: &str| {
+            let rendered = Markdown::parse(body, 1, &MarkdownExtensions::default()).unwrap();
+            ParsedMarkdown {
+                front_matter: toml::from_str("title = \"Post\"\ndate = \"2024-01-01\"\n").unwrap(),
+                content: rendered.markdown,
+                html_content: rendered.html,
+                toc: rendered.toc,
+                shortcodes: rendered.shortcodes,
+                path: PathBuf::from("post.md"),
+            }
+            .excerpt(200)
+        };
+
+        assert_eq!(
+            excerpt("{{< youtube id=\"abc\" >}}\n\nThe *actual* intro.\n\nMore.\n"),
+            "The actual intro."
+        );
+        assert_eq!(
+            excerpt("{{< callout kind=\"warning\" title=\"Old post\" >}}\nThis is out of date.\n{{< /callout >}}\nThe intro.\n"),
+            "The intro."
+        );
+        assert_eq!(
+            excerpt("The intro,\n{{< bluesky uri=\"at://did:plc:abc/app.bsky.feed.post/1\" >}}\nthen more.\n"),
+            "The intro,"
+        );
+    }
+
+    #[test]
+    fn marked_blockquotes_become_callouts() {
+        let rendered = Markdown::parse(
+            "> [!NOTE]\n> Plain.\n\n> [!warning] Mind *the* gap\n> First line\n> second.\n\n> [!TIP]-\n> Hidden.\n\n> [!TIP]+ Shown\n> > [!CAUTION]\n> > Nested.\n\n> [!TODO]\n> Unknown.\n",
+            1,
+            &MarkdownExtensions::default(),
+        )
+        .unwrap();
+        let html = rendered.html;
+
+        assert!(html.contains(
+            "<aside class='callout note'>\n<p class='callout-title'>Note</p>\n<p>Plain.</p>\n</aside>"
+        ));
+        assert!(html.contains(
+            "<aside class='callout warning'>\n<p class='callout-title'>Mind <em>the</em> gap</p>\n<p>First line\nsecond.</p>\n</aside>"
+        ));
+        assert!(html.contains(
+            "<details class='callout tip'>\n<summary class='callout-title'>Tip</summary>\n<p>Hidden.</p>\n</details>"
+        ));
+        assert!(html.contains(
+            "<details class='callout tip' open>\n<summary class='callout-title'>Shown</summary>\n<aside class='callout caution'>"
+        ));
+        assert!(html.contains("<blockquote>\n<p>[!TODO]\nUnknown.</p>\n</blockquote>"));
+    }
+
+    #[test]
+    fn posts_override_site_extensions() {
+        let site = MarkdownExtensions {
+            footnotes: Some(true),
+            tables: Some(false),
+            ..MarkdownExtensions::default()
+        };
+        let post = MarkdownExtensions {
+            footnotes: Some(false),
+            ..MarkdownExtensions::default()
+        };
+        let options = site.overridden_by(&post).options();
+
+        assert!(!options.contains(Options::ENABLE_FOOTNOTES));
+        assert!(!options.contains(Options::ENABLE_TABLES));
+        assert!(options.contains(Options::ENABLE_STRIKETHROUGH));
+    }
+}
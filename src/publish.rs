@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+
+use crate::context::AppContext;
+use crate::services::blue_sky::Announcement;
+
+/// Bluesky caps link card descriptions well above this, but shorter reads
+/// better in the feed.
+const DESCRIPTION_LENGTH: usize = 200;
+
+/// Announces every post dated on or after `bluesky.announce_since` that
+/// doesn't have a `bluesky_uri` yet, oldest first, and records the new AT
+/// URI in its `index.toml` entry. With `dry_run` the announcements are only
+/// printed.
+///
+/// Run this once the site is deployed, so the links resolve.
+pub async fn publish(cx: &AppContext, dry_run: bool) -> Result<()> {
+    let config = &cx.config().bluesky;
+    let since = config.announce_since().ok_or_else(|| {
+        anyhow!("Set `bluesky.announce_since` to the date of the first post to announce")
+    })?;
+    if cx.offline() && !dry_run {
+        return Err(anyhow!("Can't publish to Bluesky while offline"));
+    }
+
+    let pending = {
+        let content_sources = cx.content_sources().read().await;
+        let mut pending = content_sources
+            .posts_collection()
+            .posts()
+            .into_iter()
+            .filter(|post| post.front_matter.bluesky_uri.is_none())
+            .filter(|post| post.front_matter.date.to_utc() >= since)
+            .map(|post| {
+                let announcement = Announcement {
+                    title: post.front_matter.title.clone(),
+                    description: post.excerpt(DESCRIPTION_LENGTH),
+                    url: cx.absolute_url(&format!("{}.html", post.slug()))?,
+                };
+                Ok((post.path.clone(), announcement))
+            })
+            .collect::<Result<Vec<(PathBuf, Announcement)>>>()?;
+        pending.reverse();
+        pending
+    };
+
+    if pending.is_empty() {
+        println!("No new posts to announce");
+        return Ok(());
+    }
+
+    for (path, announcement) in pending {
+        if dry_run {
+            println!(
+                "Would announce {:?}\n  {}\n  {}",
+                announcement.title, announcement.url, announcement.description
+            );
+            continue;
+        }
+
+        let uri = cx
+            .blue_sky()
+            .write()
+            .await
+            .announce(config, &announcement)
+            .await?;
+
+        cx.content_sources()
+            .write()
+            .await
+            .posts_collection_mut()
+            .record_announcement(&path, &uri)
+            .with_context(|| {
+                format!(
+                    "Announced {:?} at {} but couldn't record it, add `bluesky_uri = {:?}` to its index.toml entry before publishing again",
+                    announcement.title, uri, uri
+                )
+            })?;
+        println!("Announced {:?} at {}", announcement.title, uri);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use log::{debug, error, info};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tower_http::services::ServeDir;
+
+use crate::build;
+use crate::context::AppContext;
+use crate::services::{content::ContentSources, site_generator::SiteGenerator, Service};
+
+pub const DEFAULT_PORT: u16 = 4000;
+
+/// Pages built for the dev server listen here for reload events, see
+/// `templates/base.html`.
+pub const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+/// Changes arriving within this window are batched into a single rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Builds the site, serves the output directory on localhost and rebuilds
+/// whenever content, includes or templates change.
+pub async fn serve(cx: Arc<AppContext>, port: u16) -> Result<()> {
+    cx.site_generator().write().await.set_live_reload(true);
+    build::build(&cx).await?;
+
+    let (reload_tx, _) = broadcast::channel::<()>(16);
+
+    // Watching stops when the watcher is dropped
+    let _watcher = watch(cx.clone(), reload_tx.clone())?;
+
+    let app = Router::new()
+        .route(
+            LIVE_RELOAD_PATH,
+            get(move || live_reload(reload_tx.subscribe())),
+        )
+        .fallback_service(ServeDir::new(cx.output_dir()));
+
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind to {}", address))?;
+
+    info!("Serving {:?} at http://{}", cx.output_dir(), address);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// An event stream that sends a message to the page every time the site is
+/// rebuilt.
+async fn live_reload(
+    receiver: broadcast::Receiver<()>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(receiver)
+        .filter_map(|message| message.ok().map(|_| Ok(Event::default().data("reload"))));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn watch(cx: Arc<AppContext>, reload_tx: broadcast::Sender<()>) -> Result<RecommendedWatcher> {
+    let (change_tx, mut change_rx) = mpsc::unbounded_channel();
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if !event.kind.is_access() => {
+                change_tx.send(event).ok();
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to watch for changes: {:?}", e),
+        })?;
+
+    for dir in [cx.content_dir(), cx.includes_dir(), cx.templates_dir()] {
+        watcher
+            .watch(&dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {:?}", dir))?;
+        info!("Watching {:?}", dir);
+    }
+
+    tokio::spawn(async move {
+        while let Some(event) = change_rx.recv().await {
+            debug!("Change detected: {:?}", event.paths);
+            tokio::time::sleep(DEBOUNCE).await;
+            while change_rx.try_recv().is_ok() {}
+
+            match rebuild(&cx).await {
+                Ok(()) => {
+                    info!("Rebuilt site, reloading pages");
+                    reload_tx.send(()).ok();
+                }
+                Err(e) => error!("Failed to rebuild site: {:?}", e),
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Reloads content and reassembles the pages. Bluesky posts are only
+/// fetched on startup.
+async fn rebuild(cx: &Arc<AppContext>) -> Result<()> {
+    cx.services()
+        .update_only(cx, &[ContentSources::NAME, SiteGenerator::NAME])
+        .await?;
+    build::build(cx).await
+}
@@ -4,6 +4,7 @@ use crate::context::AppContext;
 
 pub mod blue_sky;
 pub mod content;
+pub mod registry;
 pub mod site_generator;
 
 #[async_trait]
@@ -13,8 +14,13 @@ pub trait UpdateableService: Service + Send + Sync {
 
 #[async_trait]
 pub trait Service {
+    /// Identifies the service in logs and in other services' dependencies.
+    const NAME: &'static str;
+
+    /// Services that have to finish updating before this one starts.
+    const DEPENDENCIES: &'static [&'static str] = &[];
+
     async fn init() -> anyhow::Result<Self>
     where
         Self: Sized;
-    fn name(&self) -> &'static str;
 }
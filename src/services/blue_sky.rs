@@ -1,117 +1,601 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use atrium_api::{
     agent::{store::MemorySessionStore, AtpAgent},
-    app::bsky::feed::get_author_feed,
+    app::bsky::feed::{get_author_feed, get_post_thread, get_posts},
+    com::atproto::repo::create_record,
     types::{string::Handle, LimitedNonZeroU8, Object},
 };
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, fs, path::Path, time::Duration};
 
 use atrium_xrpc_client::reqwest::ReqwestClient;
-use chrono::{DateTime, Utc};
-use serde_json::Value;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, info, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use super::{Service, UpdateableService};
+use super::{content::ContentSources, Service, UpdateableService};
 
+pub mod comments;
+pub mod post;
+pub mod rich_text;
+
+#[cfg(test)]
+mod tests;
+
+use comments::Comment;
+use post::{Embed, FeedPost};
+
+use crate::config::BlueSkyConfig;
 use crate::context::AppContext;
+use crate::shortcodes;
+
+pub const DEFAULT_SERVICE: &str = "https://bsky.social";
+
+/// The most `getAuthorFeed` returns per request
+const POSTS_PER_PAGE: u8 = 100;
+
+/// Pages fetched per build while catching up on new posts. Anything older
+/// is left to `backfill`, which resumes from where the update stopped.
+const MAX_UPDATE_PAGES: usize = 5;
 
-const POSTS_PER_UPDATE: u8 = 30;
+/// Pause between page requests to stay well clear of the API rate limits.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How many levels of replies to fetch under an announcement
+const COMMENT_DEPTH: u16 = 6;
+
+/// Where mirrored images are written, relative to the output directory
+const ASSETS_DIR: &str = "assets/bluesky";
+
+/// Every post fetched so far, kept in the cache directory so builds work
+/// without credentials or a network connection.
+const CACHE_FILE: &str = "bluesky.json";
 
 pub struct BlueSky {
     agent: AtpAgent<MemorySessionStore, ReqwestClient>,
+    /// The XRPC endpoint `agent` talks to
+    service: String,
     last_updated: DateTime<Utc>,
     posts: HashMap<String, FeedPost>,
+    cache_loaded: bool,
+    /// Cursor for the page after the oldest one backfilled so far
+    backfill_cursor: Option<String>,
+    /// Set once backfill has reached the date bound or the first post
+    backfill_complete: bool,
+    /// Replies to announced blog posts, keyed by the announcement's URI
+    comments: HashMap<String, Vec<Comment>>,
+    /// Posts outside the author feed embedded in blog posts with the
+    /// `bluesky` shortcode, keyed by URI
+    embeds: HashMap<String, FeedPost>,
 }
 
-impl BlueSky {
-    pub fn new() -> Self {
-        let agent = AtpAgent::new(
-            ReqwestClient::new("https://bsky.social"),
-            MemorySessionStore::default(),
-        );
+/// The on-disk form of the cache.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BlueSkyCache {
+    posts: Vec<FeedPost>,
+    #[serde(default)]
+    backfill_cursor: Option<String>,
+    #[serde(default)]
+    backfill_complete: bool,
+    #[serde(default)]
+    comments: HashMap<String, Vec<Comment>>,
+    #[serde(default)]
+    embeds: HashMap<String, FeedPost>,
+}
+
+/// A link card post pointing at a new blog post.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub title: String,
+    pub description: String,
+    pub url: Url,
+}
+
+/// One page of an author feed.
+struct FeedPage {
+    posts: Vec<FeedPost>,
+    /// Where the next, older page starts, unset at the end of the feed
+    cursor: Option<String>,
+}
 
+impl BlueSky {
+    pub fn new(service: &str) -> Self {
         BlueSky {
-            agent,
+            agent: Self::agent(service),
+            service: service.to_string(),
             last_updated: Utc::now(),
             posts: HashMap::new(),
+            cache_loaded: false,
+            backfill_cursor: None,
+            backfill_complete: false,
+            comments: HashMap::new(),
+            embeds: HashMap::new(),
+        }
+    }
+
+    fn agent(service: &str) -> AtpAgent<MemorySessionStore, ReqwestClient> {
+        AtpAgent::new(ReqwestClient::new(service), MemorySessionStore::default())
+    }
+
+    /// Points the client at another XRPC endpoint. Any session with the
+    /// previous one is dropped.
+    pub fn set_service(&mut self, service: &str) {
+        if self.service != service {
+            self.agent = Self::agent(service);
+            self.service = service.to_string();
         }
     }
 }
 
 #[async_trait]
 impl UpdateableService for BlueSky {
-    async fn update(&mut self, _cx: &AppContext) -> Result<()> {
-        self.update_posts(POSTS_PER_UPDATE)
-            .await
-            .map_err(|e| anyhow!("{}", e))?;
-        Ok(())
+    /// Loads cached posts, then fetches new ones, the replies to announced
+    /// blog posts and any embedded posts not cached yet unless running
+    /// offline. Missing credentials or a failed fetch fall back to the cache.
+    async fn update(&mut self, cx: &AppContext) -> Result<()> {
+        let cache_path = cx.cache_dir().join(CACHE_FILE);
+        if !self.cache_loaded {
+            self.load_cache(&cache_path);
+            self.cache_loaded = true;
+        }
+
+        if cx.offline() {
+            info!("Offline, using {} cached Bluesky posts", self.posts.len());
+            self.mirror_images(&cx.output_dir(), false).await;
+            return Ok(());
+        }
+
+        let (announcements, embeds) = {
+            let content_sources = cx.content_sources().read().await;
+            let posts = content_sources.posts_collection().posts();
+            let announcements = posts
+                .iter()
+                .filter_map(|post| post.front_matter.bluesky_uri.clone())
+                .collect::<Vec<_>>();
+            let embeds = posts
+                .iter()
+                .flat_map(|post| shortcodes::bluesky_uris(&post.shortcodes))
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            (announcements, embeds)
+        };
+
+        self.set_service(&cx.config().bluesky.service);
+        let result = async {
+            self.ensure_session().await?;
+            self.update_posts(&cx.config().bluesky, &cache_path).await?;
+            self.update_comments(&announcements).await;
+            self.update_embeds(&embeds).await;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            warn!(
+                "Failed to fetch Bluesky posts, using {} cached posts: {}",
+                self.posts.len(),
+                e
+            );
+        }
+
+        self.mirror_images(&cx.output_dir(), true).await;
+        self.save_cache(&cache_path)
     }
 }
 
 #[async_trait]
 impl Service for BlueSky {
-    /// Initialize the Blue Sky client and create a session.
+    const NAME: &'static str = "BlueSky";
+    /// Replies are fetched for posts announced on Bluesky
+    const DEPENDENCIES: &'static [&'static str] = &[ContentSources::NAME];
+
+    /// Initialize the Blue Sky client. The session is created on the first
+    /// update, so commands that never fetch posts don't need credentials.
     async fn init() -> Result<Self> {
-        let blue_sky_username = env::var("BLUE_SKY_USERNAME").expect("BLUE_SKY_USERNAME not set");
-        let blue_sky_password = env::var("BLUE_SKY_PASSWORD").expect("BLUE_SKY_PASSWORD not set");
+        Ok(Self::new(DEFAULT_SERVICE))
+    }
+}
 
-        let blue_sky = Self::new();
+impl BlueSky {
+    /// Creates a session using `BLUE_SKY_USERNAME` and `BLUE_SKY_PASSWORD`.
+    async fn login(&self) -> Result<()> {
+        let blue_sky_username =
+            env::var("BLUE_SKY_USERNAME").map_err(|_| anyhow!("BLUE_SKY_USERNAME not set"))?;
+        let blue_sky_password =
+            env::var("BLUE_SKY_PASSWORD").map_err(|_| anyhow!("BLUE_SKY_PASSWORD not set"))?;
 
-        blue_sky
-            .agent
-            .login(&blue_sky_username, &blue_sky_password)
-            .await?;
+        self.login_with(&blue_sky_username, &blue_sky_password)
+            .await
+    }
 
-        Ok(blue_sky)
+    pub async fn login_with(&self, username: &str, password: &str) -> Result<()> {
+        self.agent.login(username, password).await?;
+        Ok(())
     }
 
-    fn name(&self) -> &'static str {
-        "BlueSky"
+    async fn ensure_session(&self) -> Result<()> {
+        if self.agent.get_session().await.is_none() {
+            self.login().await?;
+        }
+        Ok(())
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct FeedPost {
-    pub handle: String,
-    pub text: String,
-    pub created_at: DateTime<Utc>,
-    pub uri: String,
-    pub attachments: Vec<String>,
-}
+    /// Fetches new posts and merges them into the cache at `cache_path`,
+    /// following cursors until a page reaches a cached post, the `since`
+    /// date or `MAX_UPDATE_PAGES`. Posts that were already cached are
+    /// replaced, so edits are picked up.
+    pub async fn update_posts(&mut self, config: &BlueSkyConfig, cache_path: &Path) -> Result<()> {
+        self.update_posts_within(config, cache_path, MAX_UPDATE_PAGES)
+            .await
+    }
 
-impl BlueSky {
-    pub async fn update_posts(&mut self, limit: u8) -> anyhow::Result<()> {
-        let new_posts = self.fetch_posts(limit).await?;
-        for post in new_posts {
-            if !self.posts.contains_key(&post.uri) {
-                self.posts.insert(post.uri.clone(), post);
+    /// `update_posts` with at most `max_pages` requests. Stopping at the
+    /// limit would leave a gap between the new posts and the cached ones, so
+    /// the cursor is handed to `backfill` to fill it.
+    async fn update_posts_within(
+        &mut self,
+        config: &BlueSkyConfig,
+        cache_path: &Path,
+        max_pages: usize,
+    ) -> Result<()> {
+        let since = config.since();
+        let mut cursor = None;
+        let mut fetched = 0;
+
+        for page_number in 0..max_pages {
+            if page_number > 0 {
+                tokio::time::sleep(REQUEST_INTERVAL).await;
+            }
+
+            let page = self.fetch_page(&config.handle, cursor).await?;
+            // Reposts can bring back old posts, so only the author's own count
+            let reached_cache = page
+                .posts
+                .iter()
+                .any(|post| post.reposted_by.is_none() && self.posts.contains_key(&post.uri));
+            let reached_since = Self::reached_since(&page, since);
+
+            fetched += page.posts.len();
+            self.merge_posts(page.posts, since);
+            cursor = page.cursor;
+
+            if reached_cache || reached_since || cursor.is_none() {
+                break;
+            }
+            if page_number + 1 == max_pages {
+                warn!(
+                    "Stopped after {} pages of new Bluesky posts without reaching cached ones, run `homebase backfill` to fetch the rest",
+                    max_pages
+                );
+                self.backfill_cursor = cursor.take();
+                self.backfill_complete = false;
             }
         }
+
+        info!("Fetched {} Bluesky posts", fetched);
         self.last_updated = Utc::now();
+        self.save_cache(cache_path)
+    }
+
+    /// Refetches the replies to each announcement in `uris`. A thread that
+    /// fails to load keeps its cached replies.
+    pub async fn update_comments(&mut self, uris: &[String]) {
+        for (index, uri) in uris.iter().enumerate() {
+            if index > 0 {
+                tokio::time::sleep(REQUEST_INTERVAL).await;
+            }
+
+            match self.fetch_comments(uri).await {
+                Ok(comments) => {
+                    debug!("Fetched {} replies to {}", comments.len(), uri);
+                    self.comments.insert(uri.clone(), comments);
+                }
+                Err(e) => warn!("Failed to fetch replies to {}: {}", uri, e),
+            }
+        }
+    }
+
+    /// Fetches the thread under `uri` with `getPostThread`.
+    async fn fetch_comments(&self, uri: &str) -> Result<Vec<Comment>> {
+        let params: get_post_thread::Parameters = serde_json::from_value(json!({
+            "uri": uri,
+            "depth": COMMENT_DEPTH,
+            "parentHeight": 0,
+        }))?;
+        let response = self.agent.api.app.bsky.feed.get_post_thread(params).await?;
+        let thread: Value = serde_json::to_value(&response.data.thread)?;
+        Ok(Comment::replies_from_thread(&thread))
+    }
+
+    /// Fetches each post in `uris` that isn't cached yet, so embedded posts
+    /// are only requested once.
+    pub async fn update_embeds(&mut self, uris: &[String]) {
+        let mut fetched = 0;
+        for uri in uris {
+            if self.post(uri).is_some() {
+                continue;
+            }
+            if fetched > 0 {
+                tokio::time::sleep(REQUEST_INTERVAL).await;
+            }
+
+            fetched += 1;
+            match self.fetch_post(uri).await {
+                Ok(post) => {
+                    debug!("Fetched embedded post {}", uri);
+                    self.embeds.insert(uri.clone(), post);
+                }
+                Err(e) => warn!("Failed to fetch embedded post {}: {}", uri, e),
+            }
+        }
+    }
+
+    /// A cached post by its AT URI, from the author feed or embedded in a
+    /// blog post.
+    pub fn post(&self, uri: &str) -> Option<&FeedPost> {
+        self.posts.get(uri).or_else(|| self.embeds.get(uri))
+    }
+
+    /// Fetches a single post with `getPosts`, for posts that aren't in the
+    /// author feed, like someone else's.
+    async fn fetch_post(&self, uri: &str) -> Result<FeedPost> {
+        let params: get_posts::Parameters = serde_json::from_value(json!({ "uris": [uri] }))?;
+        let response = self.agent.api.app.bsky.feed.get_posts(params).await?;
+        let view = response
+            .data
+            .posts
+            .first()
+            .ok_or_else(|| anyhow!("No post at {}", uri))?;
+        FeedPost::from_feed_item(&json!({ "post": view }))
+            .ok_or_else(|| anyhow!("Unexpected post format at {}", uri))
+    }
+
+    /// The cached replies to the announcement at `uri`, oldest first.
+    pub fn comments(&self, uri: &str) -> &[Comment] {
+        self.comments
+            .get(uri)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Walks the feed back from where the last backfill stopped, saving the
+    /// cache after every page so an interrupted run picks up where it left
+    /// off. Stops at the `since` date or the first post.
+    pub async fn backfill(&mut self, cx: &AppContext) -> Result<()> {
+        let cache_path = cx.cache_dir().join(CACHE_FILE);
+        if !self.cache_loaded {
+            self.load_cache(&cache_path);
+            self.cache_loaded = true;
+        }
+
+        if self.backfill_complete {
+            info!(
+                "Bluesky backfill already complete, {} posts cached",
+                self.posts.len()
+            );
+            return Ok(());
+        }
+        if cx.offline() {
+            return Err(anyhow!("Can't backfill Bluesky posts while offline"));
+        }
+
+        let config = &cx.config().bluesky;
+        self.set_service(&config.service);
+        self.ensure_session().await?;
+        self.backfill_posts(config, &cache_path).await?;
+
+        info!(
+            "Bluesky backfill complete, {} posts cached",
+            self.posts.len()
+        );
         Ok(())
     }
 
+    /// Fetches pages from `backfill_cursor` until the `since` date or the
+    /// end of the feed, saving the cache after each one.
+    async fn backfill_posts(&mut self, config: &BlueSkyConfig, cache_path: &Path) -> Result<()> {
+        let since = config.since();
+        let mut pages = 0;
+
+        loop {
+            if pages > 0 {
+                tokio::time::sleep(REQUEST_INTERVAL).await;
+            }
+
+            let page = self
+                .fetch_page(&config.handle, self.backfill_cursor.clone())
+                .await?;
+            pages += 1;
+
+            let reached_since = Self::reached_since(&page, since);
+            info!(
+                "Backfilled page {} with {} Bluesky posts",
+                pages,
+                page.posts.len()
+            );
+            self.merge_posts(page.posts, since);
+            self.backfill_complete = reached_since || page.cursor.is_none();
+            self.backfill_cursor = page.cursor;
+            self.save_cache(cache_path)?;
+
+            if self.backfill_complete {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Downloads every image attachment, in feed and embedded posts alike,
+    /// without a copy in the output directory's assets folder. When
+    /// `download` is false, or a download fails, images missing from the
+    /// output directory fall back to the CDN.
+    async fn mirror_images(&mut self, output_dir: &Path, download: bool) {
+        let client = reqwest::Client::new();
+        let mut mirrored = 0;
+
+        for post in self.posts.values_mut().chain(self.embeds.values_mut()) {
+            let Some(embed) = &mut post.embed else {
+                continue;
+            };
+
+            for attachment in embed.attachments_mut() {
+                if attachment
+                    .local_path
+                    .as_ref()
+                    .is_some_and(|path| output_dir.join(path).is_file())
+                {
+                    continue;
+                }
+
+                attachment.local_path = None;
+                if !download {
+                    continue;
+                }
+
+                match download_image(&client, &attachment.fullsize, output_dir).await {
+                    Ok(path) => {
+                        attachment.local_path = Some(path);
+                        mirrored += 1;
+                    }
+                    Err(e) => warn!("Failed to mirror {}: {:?}", attachment.fullsize, e),
+                }
+            }
+        }
+
+        if mirrored > 0 {
+            info!("Mirrored {} Bluesky images", mirrored);
+        }
+    }
+
+    /// Creates an `app.bsky.feed.post` record with a link card for
+    /// `announcement`, returning its AT URI.
+    pub async fn announce(
+        &mut self,
+        config: &BlueSkyConfig,
+        announcement: &Announcement,
+    ) -> Result<String> {
+        self.set_service(&config.service);
+        self.ensure_session().await?;
+        let session = self
+            .agent
+            .get_session()
+            .await
+            .ok_or_else(|| anyhow!("No Bluesky session"))?;
+
+        let record = json!({
+            "$type": "app.bsky.feed.post",
+            "text": announcement.title,
+            "createdAt": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "embed": {
+                "$type": "app.bsky.embed.external",
+                "external": {
+                    "uri": announcement.url.as_str(),
+                    "title": announcement.title,
+                    "description": announcement.description,
+                },
+            },
+        });
+        let input: create_record::Input = serde_json::from_value(json!({
+            "repo": session.did,
+            "collection": "app.bsky.feed.post",
+            "record": record,
+        }))?;
+
+        let output = self.agent.api.com.atproto.repo.create_record(input).await?;
+        Ok(output.data.uri.clone())
+    }
+
+    /// Whether every post on `page` is older than `since`. Reposts are
+    /// ignored since they carry the original post's date.
+    fn reached_since(page: &FeedPage, since: Option<DateTime<Utc>>) -> bool {
+        let Some(since) = since else {
+            return false;
+        };
+        page.posts
+            .iter()
+            .filter(|post| post.reposted_by.is_none())
+            .map(|post| post.created_at)
+            .max()
+            .is_some_and(|newest| newest < since)
+    }
+
+    /// Adds posts to the in-memory cache, skipping any older than `since`.
+    fn merge_posts(&mut self, posts: Vec<FeedPost>, since: Option<DateTime<Utc>>) {
+        for post in posts {
+            if since.is_some_and(|since| post.created_at < since) {
+                continue;
+            }
+            self.posts.insert(post.uri.clone(), post);
+        }
+    }
+
+    /// Merges posts from the cache at `path`. A missing or unreadable cache
+    /// is treated as empty.
+    fn load_cache(&mut self, path: &Path) {
+        let Ok(content) = fs::read_to_string(path) else {
+            debug!("No Bluesky cache at {:?}", path);
+            return;
+        };
+
+        match serde_json::from_str::<BlueSkyCache>(&content) {
+            Ok(cache) => {
+                debug!("Loaded {} Bluesky posts from {:?}", cache.posts.len(), path);
+                for post in cache.posts {
+                    self.posts.entry(post.uri.clone()).or_insert(post);
+                }
+                self.backfill_cursor = cache.backfill_cursor;
+                self.backfill_complete = cache.backfill_complete;
+                for (uri, comments) in cache.comments {
+                    self.comments.entry(uri).or_insert(comments);
+                }
+                for (uri, post) in cache.embeds {
+                    self.embeds.entry(uri).or_insert(post);
+                }
+            }
+            Err(e) => warn!("Ignoring unreadable Bluesky cache {:?}: {}", path, e),
+        }
+    }
+
+    fn save_cache(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let cache = BlueSkyCache {
+            posts: self.get_ordered_posts(),
+            backfill_cursor: self.backfill_cursor.clone(),
+            backfill_complete: self.backfill_complete,
+            comments: self.comments.clone(),
+            embeds: self.embeds.clone(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&cache)?)
+            .map_err(|e| anyhow!("Failed to write Bluesky cache {:?}: {}", path, e))
+    }
+
     pub fn get_ordered_posts(&self) -> Vec<FeedPost> {
         let mut posts: Vec<FeedPost> = self.posts.values().cloned().collect();
-        posts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        posts.sort_by_key(|post| std::cmp::Reverse(post.created_at));
         posts
     }
 
-    async fn fetch_posts(&self, limit: u8) -> anyhow::Result<Vec<FeedPost>> {
-        let actor = Handle::new("nate.rip".to_string()).map_err(|e| anyhow::anyhow!("{}", e))?;
+    async fn fetch_page(&self, handle: &str, cursor: Option<String>) -> Result<FeedPage> {
+        let actor = Handle::new(handle.to_string()).map_err(|e| anyhow::anyhow!("{}", e))?;
         let parameters_data = get_author_feed::ParametersData {
             actor: actor.into(),
             limit: Some(
-                LimitedNonZeroU8::<100>::try_from(limit).map_err(|e| anyhow::anyhow!("{}", e))?,
+                LimitedNonZeroU8::<100>::try_from(POSTS_PER_PAGE)
+                    .map_err(|e| anyhow::anyhow!("{}", e))?,
             ),
-            cursor: None,
+            cursor,
             filter: None,
-            include_pins: None,
+            // Pinned posts would show up at the top of every first page
+            include_pins: Some(false),
         };
 
         let params: Object<get_author_feed::ParametersData> = parameters_data.into();
 
         let response = self.agent.api.app.bsky.feed.get_author_feed(params).await?;
+        let cursor = response.data.cursor.clone();
 
         let feed_json: Value = serde_json::to_value(response.data.feed)?;
 
@@ -119,85 +603,112 @@ impl BlueSky {
             .as_array()
             .ok_or_else(|| anyhow::anyhow!("Expected feed to be an array"))?
             .iter()
-            .filter_map(|item| {
-                let post = item.get("post")?;
-                let record = post.get("record")?;
+            .filter_map(FeedPost::from_feed_item)
+            .collect();
 
-                // For now, just filter out reposts, replies, and quote posts
-                if item.get("reason").is_some() {
-                    return None;
-                }
+        Ok(FeedPage { posts, cursor })
+    }
 
-                if record.get("reply").is_some() {
-                    return None;
-                }
+    /// The posts to show, newest first, filtered by the kinds enabled in
+    /// `config`. Self-threads are folded into the post that started them.
+    pub fn entries(&self, config: &BlueSkyConfig) -> Vec<FeedPost> {
+        let posts = self.get_ordered_posts();
+        let mut entries = Vec::new();
+        let mut continuations: HashMap<String, Vec<FeedPost>> = HashMap::new();
 
-                if let Some(embed) = post.get("embed") {
-                    if embed.get("$type").map_or(false, |t| {
-                        t.as_str().unwrap_or("") == "app.bsky.embed.record"
-                    }) {
-                        return None;
-                    }
-                }
+        for mut post in posts {
+            if post.reposted_by.is_some() && !config.reposts {
+                continue;
+            }
+            if post.embed.as_ref().and_then(Embed::quoted).is_some() && !config.quotes {
+                continue;
+            }
+            if !config.link_cards && post.embed.as_ref().is_some_and(Embed::has_link_card) {
+                post.embed = post.embed.as_ref().and_then(Embed::without_link_cards);
+            }
 
-                let author = post.get("author")?;
-                let handle = author.get("handle")?.as_str()?.to_string();
-                let text = record.get("text")?.as_str()?.to_string();
-                let created_at = record.get("createdAt")?.as_str()?;
-                let created_at = DateTime::parse_from_rfc3339(created_at)
-                    .ok()?
-                    .with_timezone(&Utc);
-                let uri = post.get("uri")?.as_str()?.to_string();
-
-                let mut attachments = Vec::new();
-                if let Some(embed) = post.get("embed") {
-                    if let Some(images) = embed.get("images") {
-                        if let Some(images_array) = images.as_array() {
-                            for image in images_array {
-                                if let Some(full_size) = image.get("fullsize") {
-                                    if let Some(url) = full_size.as_str() {
-                                        attachments.push(url.to_string());
-                                    }
-                                }
-                            }
-                        }
+            if post.is_self_reply() {
+                let root_uri = post
+                    .reply
+                    .as_ref()
+                    .map(|reply| reply.root_uri.clone())
+                    .unwrap_or_default();
+                if self.posts.contains_key(&root_uri) {
+                    if config.threads {
+                        continuations.entry(root_uri).or_default().push(post);
                     }
+                    continue;
                 }
+            } else if post.reply.is_some() && !config.replies {
+                continue;
+            }
 
-                Some(FeedPost {
-                    handle,
-                    text,
-                    created_at,
-                    uri,
-                    attachments,
-                })
-            })
-            .collect();
+            entries.push(post);
+        }
 
-        Ok(posts)
+        for entry in &mut entries {
+            if let Some(mut thread) = continuations.remove(&entry.uri) {
+                thread.sort_by_key(|post| post.created_at);
+                entry.thread = thread;
+            }
+        }
+        entries
     }
 
-    pub fn render_posts(&self) -> String {
-        let posts = self.get_ordered_posts();
-        posts
+    /// Renders the newest `limit` entries, or all of them, newest first.
+    pub fn render_posts(
+        &self,
+        config: &BlueSkyConfig,
+        limit: Option<usize>,
+        date_format: &str,
+    ) -> Vec<String> {
+        let entries = self.entries(config);
+        entries
             .iter()
-            .map(|post| {
-                let mut rendered = format!("<article>");
-                rendered.push_str(&format!("<div><p>{}</p>", post.text));
-                if !post.attachments.is_empty() {
-                    rendered.push_str("<div class=\"attachments\">");
-                    for attachment in &post.attachments {
-                        rendered
-                            .push_str(&format!("<img src=\"{}\" alt=\"Attachment\">", attachment));
-                    }
-                    rendered.push_str("</div>");
-                }
-                rendered.push_str("</div>");
-                rendered.push_str(&format!("<p>@{}</p>", post.handle));
-                rendered.push_str("</article>");
-                rendered
-            })
-            .collect::<Vec<String>>()
-            .join("\n\n")
+            .take(limit.unwrap_or(entries.len()))
+            .map(|post| post.render(date_format))
+            .collect()
+    }
+}
+
+/// Downloads the image at `url` into `ASSETS_DIR`, named after the hash of
+/// its contents. Returns its path relative to the site root.
+/// The file extension for an image, from its content type or else the
+/// `@jpeg` and the like that CDN URLs end in. Anything unrecognised is saved
+/// as a JPEG rather than trusting the URL.
+fn image_extension(content_type: &str, url: &str) -> &'static str {
+    let format = match content_type.strip_prefix("image/") {
+        Some(format) => format,
+        None => url.rsplit_once('@').map_or("", |(_, format)| format),
+    };
+    match format {
+        "png" => "png",
+        "webp" => "webp",
+        "gif" => "gif",
+        _ => "jpg",
+    }
+}
+
+async fn download_image(client: &reqwest::Client, url: &str, output_dir: &Path) -> Result<String> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let bytes = response.bytes().await?;
+
+    let extension = image_extension(&content_type, url);
+    let hash = blake3::hash(&bytes).to_hex();
+    let path = format!("{}/{}.{}", ASSETS_DIR, &hash[..32], extension);
+    let destination = output_dir.join(&path);
+    if !destination.is_file() {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&destination, &bytes)
+            .with_context(|| format!("Failed to write {:?}", destination))?;
     }
+    Ok(path)
 }
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::rich_text::RichText;
+use crate::utils::html::escape_html;
+
+/// Labels that remove a reply, and everything under it, from the page.
+const HIDE_LABELS: &[&str] = &["!hide", "!takedown", "spam"];
+
+/// Labels that put a reply behind a click-through warning.
+const WARN_LABELS: &[&str] = &["!warn", "porn", "sexual", "nudity", "graphic-media", "gore"];
+
+/// A reply in the Bluesky thread announcing a blog post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub uri: String,
+    pub handle: String,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub text: RichText,
+    pub created_at: DateTime<Utc>,
+    pub like_count: u64,
+    /// The first warning label on the reply or its author, if any
+    pub warning: Option<String>,
+    /// Oldest first
+    pub replies: Vec<Comment>,
+}
+
+/// The `bsky.app` link for a post URI, which accepts a DID in place of a
+/// handle.
+pub fn thread_url(uri: &str) -> String {
+    let path = uri.trim_start_matches("at://");
+    let author = path.split('/').next().unwrap_or_default();
+    let record_key = path.rsplit('/').next().unwrap_or_default();
+    format!("https://bsky.app/profile/{}/post/{}", author, record_key)
+}
+
+fn labels(value: &Value) -> impl Iterator<Item = &str> {
+    value
+        .get("labels")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|label| label.get("val")?.as_str())
+}
+
+impl Comment {
+    /// Parses the replies of an `app.bsky.feed.defs#threadViewPost`, leaving
+    /// out deleted, blocked and hidden posts.
+    pub fn replies_from_thread(thread: &Value) -> Vec<Self> {
+        let mut replies = thread
+            .get("replies")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Self::from_thread)
+            .collect::<Vec<_>>();
+        replies.sort_by_key(|reply| reply.created_at);
+        replies
+    }
+
+    fn from_thread(thread: &Value) -> Option<Self> {
+        if thread.get("$type")?.as_str()? != "app.bsky.feed.defs#threadViewPost" {
+            return None;
+        }
+
+        let post = thread.get("post")?;
+        let author = post.get("author")?;
+        let mut warning = None;
+        for label in labels(post).chain(labels(author)) {
+            if HIDE_LABELS.contains(&label) {
+                return None;
+            }
+            if warning.is_none() && WARN_LABELS.contains(&label) {
+                warning = Some(label.trim_start_matches('!').to_string());
+            }
+        }
+
+        let record = post.get("record")?;
+        let string = |value: &Value, key: &str| Some(value.get(key)?.as_str()?.to_string());
+
+        Some(Self {
+            uri: string(post, "uri")?,
+            handle: string(author, "handle")?,
+            display_name: string(author, "displayName").filter(|name| !name.trim().is_empty()),
+            avatar: string(author, "avatar"),
+            text: RichText::from_record(record)?,
+            created_at: DateTime::parse_from_rfc3339(record.get("createdAt")?.as_str()?)
+                .ok()?
+                .with_timezone(&Utc),
+            like_count: post.get("likeCount").and_then(Value::as_u64).unwrap_or(0),
+            warning,
+            replies: Self::replies_from_thread(thread),
+        })
+    }
+
+    /// The number of replies in this comment's subtree, itself included.
+    pub fn count(&self) -> usize {
+        1 + self.replies.iter().map(Comment::count).sum::<usize>()
+    }
+
+    /// Renders the comment and its replies as a nested `<li>`.
+    pub fn render(&self, date_format: &str) -> String {
+        let avatar = self
+            .avatar
+            .as_ref()
+            .map(|avatar| {
+                format!(
+                    "<img class='avatar' src='{}' alt='' width='32' height='32' loading='lazy'>",
+                    escape_html(avatar)
+                )
+            })
+            .unwrap_or_default();
+        let name = self
+            .display_name
+            .as_ref()
+            .map(|name| format!("<strong>{}</strong> ", escape_html(name)))
+            .unwrap_or_default();
+        let likes = match self.like_count {
+            0 => String::new(),
+            1 => "<span class='likes'>1 like</span>".to_string(),
+            count => format!("<span class='likes'>{} likes</span>", count),
+        };
+
+        let text = format!("<p>{}</p>", self.text.to_html());
+        let body = match &self.warning {
+            Some(warning) => format!(
+                "<details><summary>Content warning: {}</summary>{}</details>",
+                escape_html(warning),
+                text
+            ),
+            None => text,
+        };
+
+        let replies = if self.replies.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<ul class='comments'>{}</ul>",
+                self.replies
+                    .iter()
+                    .map(|reply| reply.render(date_format))
+                    .collect::<String>()
+            )
+        };
+
+        format!(
+            "<li class='comment'><header>{}<a href='https://bsky.app/profile/{}'>{}@{}</a></header>{}<footer><a href='{}'><time datetime='{}'>{}</time></a>{}</footer>{}</li>",
+            avatar,
+            escape_html(&self.handle),
+            name,
+            escape_html(&self.handle),
+            body,
+            escape_html(&thread_url(&self.uri)),
+            self.created_at.to_rfc3339(),
+            self.created_at.format(date_format),
+            likes,
+            replies
+        )
+    }
+}
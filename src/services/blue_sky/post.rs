@@ -0,0 +1,440 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::rich_text::{web_url, RichText};
+use crate::utils::html::escape_html;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedPost {
+    pub handle: String,
+    pub text: RichText,
+    pub created_at: DateTime<Utc>,
+    /// The post's `at://` URI
+    pub uri: String,
+    #[serde(default)]
+    pub embed: Option<Embed>,
+    /// Set when the post replies to another post
+    #[serde(default)]
+    pub reply: Option<ReplyRef>,
+    /// The handle that reposted this post into the feed
+    #[serde(default)]
+    pub reposted_by: Option<String>,
+    /// Self-replies continuing this post, oldest first. Filled in when the
+    /// feed is assembled, never cached.
+    #[serde(skip)]
+    pub thread: Vec<FeedPost>,
+}
+
+/// The post a reply responds to and the post at the top of its thread.
+/// Handles are unset when the post has been deleted or is blocked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplyRef {
+    pub root_uri: String,
+    pub root_handle: Option<String>,
+    pub parent_uri: String,
+    pub parent_handle: Option<String>,
+}
+
+/// Media or a record attached to a post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Embed {
+    Images {
+        images: Vec<Attachment>,
+    },
+    /// A link card
+    External {
+        card: ExternalCard,
+    },
+    /// A quote post
+    Record {
+        quoted: QuotedPost,
+    },
+    /// A quote post along with images or a link card
+    RecordWithMedia {
+        quoted: QuotedPost,
+        media: Box<Embed>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub thumb: String,
+    pub fullsize: String,
+    /// The author's description of the image
+    pub alt: String,
+    #[serde(default)]
+    pub aspect_ratio: Option<AspectRatio>,
+    /// The mirrored copy of `fullsize`, relative to the site root
+    #[serde(default)]
+    pub local_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalCard {
+    pub uri: String,
+    pub title: String,
+    pub description: String,
+    pub thumb: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotedPost {
+    pub uri: String,
+    pub handle: String,
+    pub text: RichText,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// The lexicon type of a view, without the `#view` style suffix.
+fn view_type(value: &Value) -> Option<&str> {
+    value.get("$type")?.as_str()?.split('#').next()
+}
+
+fn string(value: &Value, key: &str) -> Option<String> {
+    Some(value.get(key)?.as_str()?.to_string())
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    Some(
+        DateTime::parse_from_rfc3339(value)
+            .ok()?
+            .with_timezone(&Utc),
+    )
+}
+
+/// The post on bsky.app, built from the record key at the end of `uri`.
+fn permalink(handle: &str, uri: &str) -> String {
+    let record_key = uri.rsplit('/').next().unwrap_or_default();
+    format!("https://bsky.app/profile/{}/post/{}", handle, record_key)
+}
+
+impl FeedPost {
+    /// Parses an item of `app.bsky.feed.getAuthorFeed`.
+    pub fn from_feed_item(item: &Value) -> Option<Self> {
+        let post = item.get("post")?;
+        let record = post.get("record")?;
+
+        let reposted_by = item
+            .get("reason")
+            .filter(|reason| {
+                reason.get("$type").and_then(Value::as_str)
+                    == Some("app.bsky.feed.defs#reasonRepost")
+            })
+            .and_then(|reason| reason.get("by"))
+            .and_then(|by| string(by, "handle"));
+
+        let reply = record.get("reply").and_then(|reply_ref| {
+            let handle = |key: &str| {
+                item.get("reply")?
+                    .get(key)?
+                    .get("author")
+                    .and_then(|author| string(author, "handle"))
+            };
+            Some(ReplyRef {
+                root_uri: string(reply_ref.get("root")?, "uri")?,
+                root_handle: handle("root"),
+                parent_uri: string(reply_ref.get("parent")?, "uri")?,
+                parent_handle: handle("parent"),
+            })
+        });
+
+        Some(FeedPost {
+            handle: string(post.get("author")?, "handle")?,
+            text: RichText::from_record(record)?,
+            created_at: parse_date(record.get("createdAt")?.as_str()?)?,
+            uri: string(post, "uri")?,
+            embed: post.get("embed").and_then(Embed::from_view),
+            reply,
+            reposted_by,
+            thread: Vec::new(),
+        })
+    }
+
+    pub fn permalink(&self) -> String {
+        permalink(&self.handle, &self.uri)
+    }
+
+    /// Whether this replies to a thread its author started, and so continues
+    /// a self-thread rather than a conversation with someone else.
+    pub fn is_self_reply(&self) -> bool {
+        self.reply.as_ref().is_some_and(|reply| {
+            reply.root_handle.as_deref() == Some(self.handle.as_str())
+                && reply.parent_handle.as_deref() == Some(self.handle.as_str())
+        })
+    }
+
+    /// Renders the post as an `<article>` with its text, embeds, any thread
+    /// continuations and a timestamp linking back to bsky.app.
+    pub fn render(&self, date_format: &str) -> String {
+        let mut rendered = String::from("<article class='note'>");
+
+        if let Some(reposted_by) = &self.reposted_by {
+            rendered.push_str(&format!(
+                "<p class='note-context'>@{} reposted @{}</p>",
+                escape_html(reposted_by),
+                escape_html(&self.handle)
+            ));
+        } else if let Some(reply) = self.reply.as_ref().filter(|_| !self.is_self_reply()) {
+            let parent = match &reply.parent_handle {
+                Some(handle) => format!(
+                    "<a href='{}'>@{}</a>",
+                    escape_html(&permalink(handle, &reply.parent_uri)),
+                    escape_html(handle)
+                ),
+                None => "a deleted post".to_string(),
+            };
+            rendered.push_str(&format!(
+                "<p class='note-context'>Replying to {}</p>",
+                parent
+            ));
+        }
+
+        rendered.push_str(&self.render_body(date_format));
+        for post in &self.thread {
+            rendered.push_str(&post.render_body(date_format));
+        }
+
+        rendered.push_str(&format!(
+            "<p class='meta-row'><a href='{}'><time datetime='{}'>{}</time></a><span>@{}</span></p></article>",
+            escape_html(&self.permalink()),
+            self.created_at.to_rfc3339(),
+            self.created_at.format(date_format),
+            escape_html(&self.handle)
+        ));
+        rendered
+    }
+
+    fn render_body(&self, date_format: &str) -> String {
+        let mut rendered = format!("<p>{}</p>", self.text.to_html());
+        if let Some(embed) = &self.embed {
+            rendered.push_str(&embed.render(date_format));
+        }
+        rendered
+    }
+}
+
+impl Embed {
+    /// Parses a post's `embed` view. Unsupported embeds, like video, and
+    /// quotes of deleted or blocked posts are skipped.
+    fn from_view(embed: &Value) -> Option<Self> {
+        match view_type(embed)? {
+            "app.bsky.embed.images" => {
+                let images = embed
+                    .get("images")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|image| {
+                        let fullsize = string(image, "fullsize")?;
+                        let aspect_ratio = image.get("aspectRatio").and_then(|ratio| {
+                            Some(AspectRatio {
+                                width: ratio.get("width")?.as_u64()?.try_into().ok()?,
+                                height: ratio.get("height")?.as_u64()?.try_into().ok()?,
+                            })
+                        });
+                        Some(Attachment {
+                            thumb: string(image, "thumb").unwrap_or_else(|| fullsize.clone()),
+                            alt: string(image, "alt").unwrap_or_default(),
+                            aspect_ratio,
+                            local_path: None,
+                            fullsize,
+                        })
+                    })
+                    .collect();
+                Some(Self::Images { images })
+            }
+            "app.bsky.embed.external" => {
+                let external = embed.get("external")?;
+                Some(Self::External {
+                    card: ExternalCard {
+                        uri: string(external, "uri")?,
+                        title: string(external, "title").unwrap_or_default(),
+                        description: string(external, "description").unwrap_or_default(),
+                        thumb: string(external, "thumb"),
+                    },
+                })
+            }
+            "app.bsky.embed.record" => Some(Self::Record {
+                quoted: QuotedPost::from_view(embed.get("record")?)?,
+            }),
+            "app.bsky.embed.recordWithMedia" => Some(Self::RecordWithMedia {
+                // The record is itself an `app.bsky.embed.record#view`
+                quoted: QuotedPost::from_view(embed.get("record")?.get("record")?)?,
+                media: Box::new(Self::from_view(embed.get("media")?)?),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The quoted post, if this embed has one.
+    pub fn quoted(&self) -> Option<&QuotedPost> {
+        match self {
+            Self::Record { quoted } | Self::RecordWithMedia { quoted, .. } => Some(quoted),
+            _ => None,
+        }
+    }
+
+    /// Whether this embed is or includes a link card.
+    pub fn has_link_card(&self) -> bool {
+        match self {
+            Self::External { .. } => true,
+            Self::RecordWithMedia { media, .. } => media.has_link_card(),
+            _ => false,
+        }
+    }
+
+    /// The images attached directly or alongside a quote.
+    pub fn attachments_mut(&mut self) -> Vec<&mut Attachment> {
+        match self {
+            Self::Images { images } => images.iter_mut().collect(),
+            Self::RecordWithMedia { media, .. } => media.attachments_mut(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A copy of this embed with link cards removed, or `None` if nothing is
+    /// left.
+    pub fn without_link_cards(&self) -> Option<Self> {
+        match self {
+            Self::External { .. } => None,
+            Self::RecordWithMedia { quoted, media } => Some(match media.without_link_cards() {
+                Some(media) => Self::RecordWithMedia {
+                    quoted: quoted.clone(),
+                    media: Box::new(media),
+                },
+                None => Self::Record {
+                    quoted: quoted.clone(),
+                },
+            }),
+            embed => Some(embed.clone()),
+        }
+    }
+
+    fn render(&self, date_format: &str) -> String {
+        match self {
+            Self::Images { images } => {
+                let mut rendered = String::from("<div class='attachments'>");
+                for image in images {
+                    rendered.push_str(&image.render());
+                }
+                rendered.push_str("</div>");
+                rendered
+            }
+            Self::External { card } => card.render(),
+            Self::Record { quoted } => quoted.render(date_format),
+            Self::RecordWithMedia { quoted, media } => {
+                format!(
+                    "{}{}",
+                    media.render(date_format),
+                    quoted.render(date_format)
+                )
+            }
+        }
+    }
+}
+
+impl Attachment {
+    /// Renders the image linking to its full size version. The mirrored copy
+    /// is used when there is one, otherwise the CDN. Mirrored paths are
+    /// relative to the site root, where notes are rendered.
+    fn render(&self) -> String {
+        let (src, href) = match &self.local_path {
+            Some(local_path) => (local_path, local_path),
+            None => (&self.thumb, &self.fullsize),
+        };
+        let size = self
+            .aspect_ratio
+            .map(|ratio| format!(" width='{}' height='{}'", ratio.width, ratio.height))
+            .unwrap_or_default();
+
+        format!(
+            "<a href='{}'><img src='{}' alt='{}'{} loading='lazy' decoding='async'></a>",
+            escape_html(href),
+            escape_html(src),
+            escape_html(&self.alt),
+            size
+        )
+    }
+}
+
+impl ExternalCard {
+    /// Renders the card as a link, or as a plain box if its URI isn't a web
+    /// page.
+    fn render(&self) -> String {
+        let thumb = self
+            .thumb
+            .as_deref()
+            .filter(|thumb| web_url(thumb).is_some())
+            .map(|thumb| format!("<img src='{}' alt='' loading='lazy'>", escape_html(thumb)))
+            .unwrap_or_default();
+        let url = web_url(&self.uri);
+        let host = url
+            .as_ref()
+            .and_then(|url| url.host_str())
+            .unwrap_or_default();
+        let content = format!(
+            "{}<strong>{}</strong><span>{}</span><small>{}</small>",
+            thumb,
+            escape_html(&self.title),
+            escape_html(&self.description),
+            escape_html(host)
+        );
+
+        match url {
+            Some(_) => format!(
+                "<a class='card link-card' href='{}'>{}</a>",
+                escape_html(&self.uri),
+                content
+            ),
+            None => format!("<div class='card link-card'>{}</div>", content),
+        }
+    }
+}
+
+impl QuotedPost {
+    /// Parses an `app.bsky.embed.record#viewRecord`.
+    fn from_view(record: &Value) -> Option<Self> {
+        if record.get("$type")?.as_str()? != "app.bsky.embed.record#viewRecord" {
+            return None;
+        }
+
+        let value = record.get("value")?;
+        Some(Self {
+            uri: string(record, "uri")?,
+            handle: string(record.get("author")?, "handle")?,
+            text: RichText::from_record(value)?,
+            created_at: value
+                .get("createdAt")
+                .and_then(Value::as_str)
+                .and_then(parse_date),
+        })
+    }
+
+    fn render(&self, date_format: &str) -> String {
+        let date = self
+            .created_at
+            .map(|date| {
+                format!(
+                    " · <time datetime='{}'>{}</time>",
+                    date.to_rfc3339(),
+                    date.format(date_format)
+                )
+            })
+            .unwrap_or_default();
+
+        format!(
+            "<blockquote class='card quote'><p>{}</p><a href='{}'>@{}{}</a></blockquote>",
+            self.text.to_html(),
+            escape_html(&permalink(&self.handle, &self.uri)),
+            escape_html(&self.handle),
+            date
+        )
+    }
+}
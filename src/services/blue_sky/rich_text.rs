@@ -0,0 +1,239 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::html::escape_html;
+
+/// Post text along with its facets, the byte-range annotations Bluesky uses
+/// for links, mentions and hashtags.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RichText {
+    pub text: String,
+    pub facets: Vec<Facet>,
+}
+
+/// A feature covering `text[start..end]`, as UTF-8 byte offsets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Facet {
+    pub start: usize,
+    pub end: usize,
+    pub feature: FacetFeature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FacetFeature {
+    Link { uri: String },
+    Mention { did: String },
+    Tag { tag: String },
+}
+
+impl FacetFeature {
+    /// Parses one entry of a facet's `features`, ignoring unknown types.
+    fn from_json(feature: &Value) -> Option<Self> {
+        let string = |key: &str| Some(feature.get(key)?.as_str()?.to_string());
+        match feature.get("$type")?.as_str()? {
+            "app.bsky.richtext.facet#link" => Some(Self::Link {
+                uri: string("uri")?,
+            }),
+            "app.bsky.richtext.facet#mention" => Some(Self::Mention {
+                did: string("did")?,
+            }),
+            "app.bsky.richtext.facet#tag" => Some(Self::Tag {
+                tag: string("tag")?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Where the facet links to. Links are taken from the post as written,
+    /// so anything but an http(s) URL is refused.
+    fn href(&self) -> Option<String> {
+        match self {
+            Self::Link { uri } => web_url(uri).map(String::from),
+            Self::Mention { did } => Some(format!("https://bsky.app/profile/{}", did)),
+            Self::Tag { tag } => {
+                let mut url = Url::parse("https://bsky.app/hashtag").expect("valid URL");
+                if let Ok(mut segments) = url.path_segments_mut() {
+                    segments.push(tag);
+                }
+                Some(url.to_string())
+            }
+        }
+    }
+}
+
+impl RichText {
+    /// Builds rich text from a post record's `text` and `facets`.
+    pub fn from_record(record: &Value) -> Option<Self> {
+        let text = record.get("text")?.as_str()?.to_string();
+
+        let mut facets = record
+            .get("facets")
+            .and_then(Value::as_array)
+            .map(|facets| {
+                facets
+                    .iter()
+                    .filter_map(|facet| {
+                        let index = facet.get("index")?;
+                        let start = index.get("byteStart")?.as_u64()? as usize;
+                        let end = index.get("byteEnd")?.as_u64()? as usize;
+                        let feature = facet
+                            .get("features")?
+                            .as_array()?
+                            .iter()
+                            .find_map(FacetFeature::from_json)?;
+                        Some(Facet {
+                            start,
+                            end,
+                            feature,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        facets.sort_by_key(|facet| facet.start);
+
+        Some(Self { text, facets })
+    }
+
+    /// Renders the text as escaped HTML with facets as anchors. Facets that
+    /// overlap an earlier one, don't fall on character boundaries or link to
+    /// something other than a web page are rendered as plain text.
+    pub fn to_html(&self) -> String {
+        let mut html = String::with_capacity(self.text.len());
+        let mut offset = 0;
+
+        for facet in &self.facets {
+            if facet.start < offset
+                || facet.start >= facet.end
+                || !self.text.is_char_boundary(facet.start)
+                || !self.text.is_char_boundary(facet.end)
+                || facet.end > self.text.len()
+            {
+                continue;
+            }
+            let Some(href) = facet.feature.href() else {
+                continue;
+            };
+
+            html.push_str(&escape_text(&self.text[offset..facet.start]));
+            html.push_str(&format!(
+                "<a href='{}'>{}</a>",
+                escape_html(&href),
+                escape_text(&self.text[facet.start..facet.end])
+            ));
+            offset = facet.end;
+        }
+
+        html.push_str(&escape_text(&self.text[offset..]));
+        html
+    }
+}
+
+/// Parses `uri` if it's an http or https URL, the only kinds of link taken
+/// from posts that are safe to put in an `href` or `src`.
+pub(super) fn web_url(uri: &str) -> Option<Url> {
+    Url::parse(uri)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Escapes plain post text, keeping its line breaks.
+fn escape_text(text: &str) -> String {
+    escape_html(text).replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(start: usize, end: usize, uri: &str) -> Facet {
+        Facet {
+            start,
+            end,
+            feature: FacetFeature::Link {
+                uri: uri.to_string(),
+            },
+        }
+    }
+
+    fn rich_text(text: &str, facets: Vec<Facet>) -> RichText {
+        RichText {
+            text: text.to_string(),
+            facets,
+        }
+    }
+
+    #[test]
+    fn escapes_text_and_links() {
+        let text = rich_text(
+            "<b>'quoted'</b> & more\nhere",
+            vec![link(18, 22, "https://example.com/?a=1&b='2'")],
+        );
+        assert_eq!(
+            text.to_html(),
+            "&lt;b&gt;&#39;quoted&#39;&lt;/b&gt; &amp; <a href='https://example.com/?a=1&amp;b=%272%27'>more</a><br>here"
+        );
+    }
+
+    #[test]
+    fn facets_are_byte_offsets_into_multibyte_text() {
+        // "🦀" is four bytes and "é" two, so "site" starts at byte 12, not 8
+        let text = rich_text(
+            "🦀 café, site #rust",
+            vec![
+                link(12, 16, "https://example.com"),
+                Facet {
+                    start: 17,
+                    end: 22,
+                    feature: FacetFeature::Tag {
+                        tag: "rust".to_string(),
+                    },
+                },
+            ],
+        );
+        assert_eq!(
+            text.to_html(),
+            "🦀 café, <a href='https://example.com/'>site</a> <a href='https://bsky.app/hashtag/rust'>#rust</a>"
+        );
+    }
+
+    #[test]
+    fn overlapping_and_out_of_range_facets_are_plain_text() {
+        let text = rich_text(
+            "one two",
+            vec![
+                link(0, 3, "https://example.com/1"),
+                link(2, 7, "https://example.com/2"),
+                link(4, 40, "https://example.com/3"),
+            ],
+        );
+        assert_eq!(
+            text.to_html(),
+            "<a href='https://example.com/1'>one</a> two"
+        );
+
+        // Inside the crab's four bytes
+        let text = rich_text("🦀 crab", vec![link(1, 4, "https://example.com")]);
+        assert_eq!(text.to_html(), "🦀 crab");
+    }
+
+    #[test]
+    fn links_other_than_web_pages_are_plain_text() {
+        for uri in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "vbscript:msgbox",
+            "//example.com",
+            "not a url",
+        ] {
+            let text = rich_text("click me", vec![link(0, 5, uri)]);
+            assert_eq!(text.to_html(), "click me", "{}", uri);
+        }
+
+        let text = rich_text("click me", vec![link(0, 5, "http://example.com")]);
+        assert_eq!(text.to_html(), "<a href='http://example.com/'>click</a> me");
+    }
+}
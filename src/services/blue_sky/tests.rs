@@ -0,0 +1,413 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tempfile::TempDir;
+
+use super::comments::Comment;
+use super::post::{Embed, FeedPost};
+use super::{image_extension, BlueSky, CACHE_FILE};
+use crate::config::BlueSkyConfig;
+use crate::utils::test_dir;
+
+macro_rules! fixture {
+    ($name:literal) => {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/bluesky/",
+            $name
+        ))
+    };
+}
+
+const CREATE_SESSION: &str = fixture!("create_session.json");
+const AUTHOR_FEED_PAGE_1: &str = fixture!("author_feed_page_1.json");
+const AUTHOR_FEED_PAGE_2: &str = fixture!("author_feed_page_2.json");
+const POST_THREAD: &str = fixture!("post_thread.json");
+const GET_POSTS: &str = fixture!("get_posts.json");
+
+const ANNOUNCEMENT_URI: &str =
+    "at://did:plc:ewvi7nxzyoun6zhxrhs64oiz/app.bsky.feed.post/3l5announce";
+const EMBEDDED_URI: &str = "at://did:plc:friendfriendfriendfriend/app.bsky.feed.post/3l5embedded";
+
+type Cursors = Arc<Mutex<Vec<Option<String>>>>;
+
+/// A local stand-in for the XRPC endpoint, serving recorded responses.
+struct MockServer {
+    url: String,
+    /// The cursor of every `getAuthorFeed` request, in order
+    cursors: Cursors,
+    /// How many `getPosts` requests were made
+    post_requests: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    async fn start() -> Self {
+        let cursors = Cursors::default();
+        let post_requests = Arc::new(AtomicUsize::new(0));
+        let requests = post_requests.clone();
+        let app = Router::new()
+            .route(
+                "/xrpc/com.atproto.server.createSession",
+                post(|| async { json(CREATE_SESSION) }),
+            )
+            .route("/xrpc/app.bsky.feed.getAuthorFeed", get(author_feed))
+            .route(
+                "/xrpc/app.bsky.feed.getPostThread",
+                get(|| async { json(POST_THREAD) }),
+            )
+            .route(
+                "/xrpc/app.bsky.feed.getPosts",
+                get(move || {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    async { json(GET_POSTS) }
+                }),
+            )
+            .with_state(cursors.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self {
+            url,
+            cursors,
+            post_requests,
+        }
+    }
+
+    fn cursors(&self) -> Vec<Option<String>> {
+        self.cursors.lock().unwrap().clone()
+    }
+
+    async fn client(&self) -> BlueSky {
+        let blue_sky = BlueSky::new(&self.url);
+        blue_sky
+            .login_with("nate.rip", "app-password")
+            .await
+            .unwrap();
+        blue_sky
+    }
+}
+
+fn json(body: &'static str) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], body)
+}
+
+async fn author_feed(
+    State(cursors): State<Cursors>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let cursor = params.get("cursor").cloned();
+    let body = match cursor.as_deref() {
+        None => AUTHOR_FEED_PAGE_1,
+        Some("page-2") => AUTHOR_FEED_PAGE_2,
+        Some(cursor) => panic!("unexpected cursor {:?}", cursor),
+    };
+    cursors.lock().unwrap().push(cursor);
+    json(body)
+}
+
+/// A cache file path in a new temporary directory, which lives as long as
+/// the returned handle.
+fn cache_path() -> (TempDir, PathBuf) {
+    let dir = test_dir();
+    let path = dir.path().join(CACHE_FILE);
+    (dir, path)
+}
+
+fn record_keys(posts: &[FeedPost]) -> Vec<&str> {
+    posts
+        .iter()
+        .map(|post| post.uri.rsplit('/').next().unwrap())
+        .collect()
+}
+
+fn comment_keys(comments: &[Comment]) -> Vec<&str> {
+    comments
+        .iter()
+        .map(|comment| comment.uri.rsplit('/').next().unwrap())
+        .collect()
+}
+
+fn find<'a>(posts: &'a [FeedPost], record_key: &str) -> &'a FeedPost {
+    posts
+        .iter()
+        .find(|post| post.uri.ends_with(record_key))
+        .unwrap_or_else(|| panic!("no post {}", record_key))
+}
+
+#[tokio::test]
+async fn parses_each_kind_of_feed_item() {
+    let server = MockServer::start().await;
+    let blue_sky = server.client().await;
+
+    let page = blue_sky.fetch_page("nate.rip", None).await.unwrap();
+    assert_eq!(page.cursor.as_deref(), Some("page-2"));
+    assert_eq!(
+        record_keys(&page.posts),
+        [
+            "3l5thread2",
+            "3l5thread1",
+            "3l5friend2",
+            "3l5reply",
+            "3l5quote",
+            "3l5image"
+        ]
+    );
+
+    let thread = find(&page.posts, "3l5thread2");
+    assert!(thread.is_self_reply());
+
+    let reply = find(&page.posts, "3l5reply");
+    assert!(!reply.is_self_reply());
+    assert_eq!(
+        reply.reply.as_ref().unwrap().parent_handle.as_deref(),
+        Some("friend.bsky.social")
+    );
+
+    let repost = find(&page.posts, "3l5friend2");
+    assert_eq!(repost.handle, "friend.bsky.social");
+    assert_eq!(repost.reposted_by.as_deref(), Some("nate.rip"));
+
+    let quote = find(&page.posts, "3l5quote");
+    let quoted = quote.embed.as_ref().and_then(Embed::quoted).unwrap();
+    assert_eq!(quoted.handle, "friend.bsky.social");
+    assert_eq!(quoted.text.text, "Small sites are good sites");
+
+    let mut image = find(&page.posts, "3l5image").clone();
+    let attachments = image.embed.as_mut().unwrap().attachments_mut();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(
+        attachments[0].alt,
+        "A wooden desk with a monitor and a plant"
+    );
+    let aspect_ratio = attachments[0].aspect_ratio.unwrap();
+    assert_eq!((aspect_ratio.width, aspect_ratio.height), (1200, 800));
+}
+
+#[tokio::test]
+async fn update_follows_cursors_until_it_reaches_cached_posts() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    let config = BlueSkyConfig::default();
+
+    blue_sky.update_posts(&config, &cache_path).await.unwrap();
+    assert_eq!(server.cursors(), [None, Some("page-2".to_string())]);
+    assert_eq!(blue_sky.get_ordered_posts().len(), 7);
+
+    // The first page is already cached, so there's no need to go further
+    blue_sky.update_posts(&config, &cache_path).await.unwrap();
+    assert_eq!(server.cursors().len(), 3);
+
+    let mut reloaded = BlueSky::new(&server.url);
+    reloaded.load_cache(&cache_path);
+    assert_eq!(reloaded.get_ordered_posts().len(), 7);
+}
+
+#[tokio::test]
+async fn default_entries_skip_replies_and_reposts_and_fold_threads() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    blue_sky
+        .update_posts(&BlueSkyConfig::default(), &cache_path)
+        .await
+        .unwrap();
+
+    let entries = blue_sky.entries(&BlueSkyConfig::default());
+    assert_eq!(
+        record_keys(&entries),
+        ["3l5thread1", "3l5quote", "3l5image", "3l5link"]
+    );
+    assert_eq!(record_keys(&entries[0].thread), ["3l5thread2"]);
+    assert!(find(&entries, "3l5link")
+        .embed
+        .as_ref()
+        .is_some_and(Embed::has_link_card));
+}
+
+#[tokio::test]
+async fn entries_respect_each_kind_toggle() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    blue_sky
+        .update_posts(&BlueSkyConfig::default(), &cache_path)
+        .await
+        .unwrap();
+
+    let config = BlueSkyConfig {
+        reposts: true,
+        replies: true,
+        threads: false,
+        quotes: false,
+        link_cards: false,
+        ..BlueSkyConfig::default()
+    };
+    let entries = blue_sky.entries(&config);
+    assert_eq!(
+        record_keys(&entries),
+        [
+            "3l5thread1",
+            "3l5friend2",
+            "3l5reply",
+            "3l5image",
+            "3l5link"
+        ]
+    );
+    assert!(entries[0].thread.is_empty());
+    assert!(find(&entries, "3l5link").embed.is_none());
+}
+
+#[tokio::test]
+async fn renders_facets_images_and_context() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    let config = BlueSkyConfig {
+        reposts: true,
+        replies: true,
+        ..BlueSkyConfig::default()
+    };
+    blue_sky.update_posts(&config, &cache_path).await.unwrap();
+    let entries = blue_sky.entries(&config);
+
+    let link = find(&entries, "3l5link").render("%Y-%m-%d");
+    assert!(link.contains("Wrote about it on <a href='https://example.com/'>my site</a>"));
+    assert!(link.contains("class='card link-card'"));
+
+    let image = find(&entries, "3l5image").render("%Y-%m-%d");
+    assert!(
+        image.contains("alt='A wooden desk with a monitor and a plant' width='1200' height='800'")
+    );
+
+    let repost = find(&entries, "3l5friend2").render("%Y-%m-%d");
+    assert!(repost.contains("@nate.rip reposted @friend.bsky.social"));
+
+    let reply = find(&entries, "3l5reply").render("%Y-%m-%d");
+    assert!(reply.contains("Replying to <a href='https://bsky.app/profile/friend.bsky.social/post/3l5friend1'>@friend.bsky.social</a>"));
+}
+
+#[tokio::test]
+async fn backfill_resumes_where_an_update_hit_the_page_limit() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    let config = BlueSkyConfig::default();
+    blue_sky.backfill_complete = true;
+
+    // One page isn't enough to reach the (empty) cache, so there's a gap
+    blue_sky
+        .update_posts_within(&config, &cache_path, 1)
+        .await
+        .unwrap();
+    assert_eq!(server.cursors(), [None]);
+
+    let mut reloaded = BlueSky::new(&server.url);
+    reloaded.load_cache(&cache_path);
+    assert_eq!(reloaded.backfill_cursor.as_deref(), Some("page-2"));
+    assert!(!reloaded.backfill_complete);
+
+    blue_sky.backfill_posts(&config, &cache_path).await.unwrap();
+    assert_eq!(server.cursors(), [None, Some("page-2".to_string())]);
+    assert_eq!(blue_sky.get_ordered_posts().len(), 7);
+    assert!(blue_sky.backfill_complete);
+}
+
+#[tokio::test]
+async fn comments_nest_replies_and_respect_labels() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    blue_sky
+        .update_comments(&[ANNOUNCEMENT_URI.to_string()])
+        .await;
+
+    // Spam is dropped, as is the deleted reply
+    let comments = blue_sky.comments(ANNOUNCEMENT_URI);
+    assert_eq!(
+        comment_keys(comments),
+        ["3l5comment1", "3l5warned", "3l5comment2"]
+    );
+    assert_eq!(comment_keys(&comments[0].replies), ["3l5comment1reply"]);
+    assert_eq!(comments.iter().map(Comment::count).sum::<usize>(), 4);
+
+    let comment = comments[0].render("%Y-%m-%d");
+    assert!(comment.contains("<img class='avatar'"));
+    assert!(comment.contains("<strong>Friend</strong> @friend.bsky.social"));
+    assert!(comment.contains("3 likes"));
+    assert!(comment.contains("<ul class='comments'><li class='comment'>"));
+
+    let warned = comments[1].render("%Y-%m-%d");
+    assert!(warned.contains("<summary>Content warning: graphic-media</summary>"));
+
+    // A reply's `javascript:` link facet is kept as text
+    let bookmarked = comments[2].render("%Y-%m-%d");
+    assert!(bookmarked.contains("<p>Bookmarked &lt;3, mirrored here</p>"));
+    assert!(!bookmarked.contains("javascript:"));
+
+    assert!(blue_sky
+        .comments("at://did:plc:unknown/app.bsky.feed.post/1")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn embedded_posts_are_fetched_once_and_cached() {
+    let server = MockServer::start().await;
+    let mut blue_sky = server.client().await;
+    let (_dir, cache_path) = cache_path();
+    blue_sky
+        .update_posts(&BlueSkyConfig::default(), &cache_path)
+        .await
+        .unwrap();
+
+    // Posts already in the author feed aren't requested again
+    let feed_post = blue_sky.get_ordered_posts()[0].uri.clone();
+    let uris = [EMBEDDED_URI.to_string(), feed_post];
+    blue_sky.update_embeds(&uris).await;
+    blue_sky.update_embeds(&uris).await;
+    assert_eq!(server.post_requests.load(Ordering::SeqCst), 1);
+
+    let embedded = blue_sky.post(EMBEDDED_URI).unwrap();
+    assert!(embedded
+        .render("%Y-%m-%d")
+        .contains("A post worth quoting in a blog post"));
+    // Embeds stay out of the notes
+    assert!(record_keys(&blue_sky.get_ordered_posts())
+        .iter()
+        .all(|key| *key != "3l5embedded"));
+
+    blue_sky.save_cache(&cache_path).unwrap();
+    let mut reloaded = BlueSky::new(&server.url);
+    reloaded.load_cache(&cache_path);
+    assert!(reloaded.post(EMBEDDED_URI).is_some());
+}
+
+#[test]
+fn image_extensions_are_limited_to_known_formats() {
+    let url = "https://cdn.bsky.app/img/feed_fullsize/plain/did:plc:abc/bafkrei";
+    assert_eq!(
+        image_extension("image/png", &format!("{}@jpeg", url)),
+        "png"
+    );
+    assert_eq!(image_extension("", &format!("{}@webp", url)), "webp");
+    assert_eq!(image_extension("", &format!("{}@jpeg", url)), "jpg");
+    assert_eq!(image_extension("image/svg+xml", url), "jpg");
+    assert_eq!(image_extension("", &format!("{}@html", url)), "jpg");
+    assert_eq!(
+        image_extension("", &format!("{}@../../index.html", url)),
+        "jpg"
+    );
+}
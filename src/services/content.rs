@@ -15,24 +15,27 @@ pub struct ContentSources {
 
 #[async_trait]
 impl Service for ContentSources {
+    const NAME: &'static str = "ContentSources";
+
+    /// Starts out empty, content is loaded from the context's content
+    /// directory on update.
     async fn init() -> Result<Self> {
-        let posts_collection =
-            PostsCollection::new(std::env::current_dir()?.join("content").join("posts"))?;
         Ok(Self {
-            posts: posts_collection,
+            posts: PostsCollection::default(),
         })
     }
-
-    fn name(&self) -> &'static str {
-        "ContentSources"
-    }
 }
 
 #[async_trait]
 impl UpdateableService for ContentSources {
-    async fn update(&mut self, _cx: &AppContext) -> Result<()> {
-        // No update logic needed for now
-        Ok(())
+    async fn update(&mut self, cx: &AppContext) -> Result<()> {
+        let posts_dir = cx.content_dir().join("posts");
+        if self.posts.src() == &posts_dir {
+            self.posts.reload()
+        } else {
+            self.posts = PostsCollection::new(posts_dir, cx.config().markdown)?;
+            Ok(())
+        }
     }
 }
 
@@ -40,4 +43,8 @@ impl ContentSources {
     pub fn posts_collection(&self) -> &PostsCollection {
         &self.posts
     }
+
+    pub fn posts_collection_mut(&mut self) -> &mut PostsCollection {
+        &mut self.posts
+    }
 }
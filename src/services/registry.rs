@@ -0,0 +1,302 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{error, info, warn};
+use std::{collections::HashSet, sync::Arc, time::Instant};
+use tokio::{sync::RwLock, task::JoinSet};
+
+use super::{Service, UpdateableService};
+use crate::context::AppContext;
+
+/// An updateable service with its type erased, so the registry can hold
+/// every kind side by side.
+#[async_trait]
+trait Updater: Send + Sync {
+    async fn update(&self, cx: &AppContext) -> Result<()>;
+}
+
+#[async_trait]
+impl<T: UpdateableService + 'static> Updater for RwLock<T> {
+    async fn update(&self, cx: &AppContext) -> Result<()> {
+        self.write().await.update(cx).await
+    }
+}
+
+#[derive(Clone)]
+struct Registration {
+    name: &'static str,
+    dependencies: &'static [&'static str],
+    service: Arc<dyn Updater>,
+}
+
+/// The services the app keeps up to date, in the order they were
+/// registered.
+#[derive(Clone, Default)]
+pub struct ServiceRegistry {
+    services: Vec<Registration>,
+}
+
+/// Initializes a service, logging how long it took.
+pub async fn init<T: Service>() -> Result<Arc<RwLock<T>>> {
+    let start = Instant::now();
+    let service = T::init()
+        .await
+        .with_context(|| format!("Failed to initialize {}", T::NAME))?;
+    info!("Initialized {} in {:.2?}", T::NAME, start.elapsed());
+    Ok(Arc::new(RwLock::new(service)))
+}
+
+impl ServiceRegistry {
+    /// Adds a service to be updated. Its dependencies have to be registered
+    /// first, which also rules out cycles.
+    pub fn register<T: UpdateableService + 'static>(
+        &mut self,
+        service: &Arc<RwLock<T>>,
+    ) -> Result<()> {
+        if self.contains(T::NAME) {
+            return Err(anyhow!("{} is already registered", T::NAME));
+        }
+        if let Some(dependency) = T::DEPENDENCIES.iter().find(|name| !self.contains(name)) {
+            return Err(anyhow!(
+                "{} depends on {}, which isn't registered",
+                T::NAME,
+                dependency
+            ));
+        }
+
+        self.services.push(Registration {
+            name: T::NAME,
+            dependencies: T::DEPENDENCIES,
+            service: service.clone(),
+        });
+        Ok(())
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.services.iter().any(|service| service.name == name)
+    }
+
+    /// Updates every registered service.
+    pub async fn update_all(&self, cx: &Arc<AppContext>) -> Result<()> {
+        let names = self
+            .services
+            .iter()
+            .map(|service| service.name)
+            .collect::<Vec<_>>();
+        self.update_only(cx, &names).await
+    }
+
+    /// Updates the named services, starting each one as soon as its
+    /// dependencies among them have finished so independent services run
+    /// concurrently. Dependencies that aren't named are left as they are.
+    ///
+    /// A failed service doesn't stop the others, but anything depending on
+    /// it is skipped and the update as a whole fails.
+    pub async fn update_only(&self, cx: &Arc<AppContext>, names: &[&str]) -> Result<()> {
+        if let Some(name) = names.iter().find(|name| !self.contains(name)) {
+            return Err(anyhow!("No service named {}", name));
+        }
+
+        let mut pending = self
+            .services
+            .iter()
+            .filter(|service| names.contains(&service.name))
+            .collect::<Vec<_>>();
+        let mut running = HashSet::new();
+        let mut failed = Vec::new();
+        let mut tasks = JoinSet::new();
+
+        loop {
+            // Registration order puts dependencies first, so skipping
+            // carries through to indirect dependents in a single pass
+            let mut index = 0;
+            while index < pending.len() {
+                let service = pending[index];
+                match service
+                    .dependencies
+                    .iter()
+                    .find(|dependency| failed.contains(*dependency))
+                {
+                    Some(dependency) => {
+                        warn!("Skipping {}, {} failed to update", service.name, dependency);
+                        failed.push(service.name);
+                        pending.remove(index);
+                    }
+                    None => index += 1,
+                }
+            }
+
+            let (ready, blocked): (Vec<_>, Vec<_>) = pending.iter().partition(|service| {
+                !service.dependencies.iter().any(|dependency| {
+                    running.contains(dependency)
+                        || pending.iter().any(|other| other.name == *dependency)
+                })
+            });
+            pending = blocked;
+
+            for service in ready {
+                running.insert(service.name);
+                let cx = cx.clone();
+                let name = service.name;
+                let service = service.service.clone();
+                tasks.spawn(async move {
+                    let start = Instant::now();
+                    let result = service.update(&cx).await;
+                    (name, start.elapsed(), result)
+                });
+            }
+
+            let Some(task) = tasks.join_next().await else {
+                break;
+            };
+            let (name, elapsed, result) = task.context("A service panicked while updating")?;
+            running.remove(name);
+            match result {
+                Ok(()) => info!("Updated {} in {:.2?}", name, elapsed),
+                Err(e) => {
+                    error!("Failed to update {} after {:.2?}: {:?}", name, elapsed, e);
+                    failed.push(name);
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to update {}", failed.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ContextOptions;
+    use std::sync::Mutex;
+
+    /// The names of services in the order they updated
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    macro_rules! test_service {
+        ($name:ident, [$($dependency:ident),*]) => {
+            struct $name {
+                log: Log,
+                fail: bool,
+            }
+
+            impl $name {
+                fn new(log: &Log, fail: bool) -> Arc<RwLock<Self>> {
+                    Arc::new(RwLock::new(Self {
+                        log: log.clone(),
+                        fail,
+                    }))
+                }
+            }
+
+            #[async_trait]
+            impl Service for $name {
+                const NAME: &'static str = stringify!($name);
+                const DEPENDENCIES: &'static [&'static str] = &[$($dependency::NAME),*];
+
+                async fn init() -> Result<Self> {
+                    Ok(Self {
+                        log: Log::default(),
+                        fail: false,
+                    })
+                }
+            }
+
+            #[async_trait]
+            impl UpdateableService for $name {
+                async fn update(&mut self, _cx: &AppContext) -> Result<()> {
+                    self.log.lock().unwrap().push(Self::NAME);
+                    if self.fail {
+                        Err(anyhow!("{} failed", Self::NAME))
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+        };
+    }
+
+    test_service!(Content, []);
+    test_service!(Feed, []);
+    test_service!(Pages, [Content, Feed]);
+    test_service!(Search, [Pages]);
+
+    fn registry(log: &Log, failing: &str) -> ServiceRegistry {
+        let mut registry = ServiceRegistry::default();
+        registry
+            .register(&Content::new(log, failing == Content::NAME))
+            .unwrap();
+        registry
+            .register(&Feed::new(log, failing == Feed::NAME))
+            .unwrap();
+        registry
+            .register(&Pages::new(log, failing == Pages::NAME))
+            .unwrap();
+        registry
+            .register(&Search::new(log, failing == Search::NAME))
+            .unwrap();
+        registry
+    }
+
+    fn position(log: &Log, name: &str) -> usize {
+        log.lock()
+            .unwrap()
+            .iter()
+            .position(|logged| *logged == name)
+            .unwrap_or_else(|| panic!("{} didn't update", name))
+    }
+
+    #[tokio::test]
+    async fn updates_dependencies_first() {
+        let cx = AppContext::new(ContextOptions::default()).await.unwrap();
+        let log = Log::default();
+        registry(&log, "").update_all(&cx).await.unwrap();
+
+        assert_eq!(log.lock().unwrap().len(), 4);
+        assert!(position(&log, Content::NAME) < position(&log, Pages::NAME));
+        assert!(position(&log, Feed::NAME) < position(&log, Pages::NAME));
+        assert!(position(&log, Pages::NAME) < position(&log, Search::NAME));
+    }
+
+    #[tokio::test]
+    async fn skips_dependents_of_failed_services() {
+        let cx = AppContext::new(ContextOptions::default()).await.unwrap();
+        let log = Log::default();
+        let error = registry(&log, Feed::NAME)
+            .update_all(&cx)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "Failed to update Feed, Pages, Search");
+        let mut updated = log.lock().unwrap().clone();
+        updated.sort();
+        assert_eq!(updated, [Content::NAME, Feed::NAME]);
+    }
+
+    #[tokio::test]
+    async fn updates_only_the_named_services() {
+        let cx = AppContext::new(ContextOptions::default()).await.unwrap();
+        let log = Log::default();
+        registry(&log, "")
+            .update_only(&cx, &[Search::NAME, Content::NAME])
+            .await
+            .unwrap();
+
+        // Pages isn't named, so nothing holds Search back
+        let mut updated = log.lock().unwrap().clone();
+        updated.sort();
+        assert_eq!(updated, [Content::NAME, Search::NAME]);
+    }
+
+    #[test]
+    fn dependencies_have_to_be_registered_first() {
+        let log = Log::default();
+        let mut registry = ServiceRegistry::default();
+        registry.register(&Content::new(&log, false)).unwrap();
+        assert!(registry.register(&Pages::new(&log, false)).is_err());
+        assert!(registry.register(&Content::new(&log, false)).is_err());
+    }
+}
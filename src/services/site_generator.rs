@@ -1,16 +1,41 @@
-use std::fs;
-
-use super::Service;
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use super::{blue_sky::BlueSky, content::ContentSources, Service, UpdateableService};
+use crate::cache::{hash_dirs, BuildCache};
+use crate::config::SiteMetadata;
+use crate::feed::{Feed, FeedOptions};
+use crate::highlight;
+use crate::markdown::{PostDate, TocEntry};
+use crate::templates::{TemplateContext, Templates};
 use crate::AppContext;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use log::{debug, error, info};
-use unindent::Unindent;
+use serde::Serialize;
 
 #[derive(Debug, Clone)]
 pub enum Layout {
     Index,
     Page,
+    Tags,
+    Tag,
+    Series,
+    /// Posts pulled from Bluesky
+    Feed,
+}
+
+impl Layout {
+    /// The file in the templates directory used to render this layout.
+    pub fn template_name(&self) -> &'static str {
+        match self {
+            Layout::Index => "index.html",
+            Layout::Page => "page.html",
+            Layout::Tags => "tags.html",
+            Layout::Tag => "tag.html",
+            Layout::Series => "series.html",
+            Layout::Feed => "feed.html",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +43,12 @@ pub struct PageBuilder {
     layout: Layout,
     title: String,
     slug: String,
+    date: Option<PostDate>,
+    updated: Option<PostDate>,
+    tags: Vec<String>,
+    series: Option<SeriesNav>,
+    comments: Option<Comments>,
+    toc: Vec<TocEntry>,
     content: Vec<String>,
 }
 
@@ -27,6 +58,12 @@ impl PageBuilder {
             layout,
             title: String::new(),
             slug: String::new(),
+            date: None,
+            updated: None,
+            tags: Vec::new(),
+            series: None,
+            comments: None,
+            toc: Vec::new(),
             content: Vec::new(),
         }
     }
@@ -41,6 +78,36 @@ impl PageBuilder {
         self
     }
 
+    pub fn date(mut self, date: PostDate) -> Self {
+        self.date = Some(date);
+        self
+    }
+
+    pub fn updated(mut self, updated: Option<PostDate>) -> Self {
+        self.updated = updated;
+        self
+    }
+
+    pub fn tags(mut self, tags: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn series(mut self, series: SeriesNav) -> Self {
+        self.series = Some(series);
+        self
+    }
+
+    pub fn comments(mut self, comments: Comments) -> Self {
+        self.comments = Some(comments);
+        self
+    }
+
+    pub fn toc(mut self, toc: Vec<TocEntry>) -> Self {
+        self.toc = toc;
+        self
+    }
+
     pub fn child(mut self, content: impl Into<String>) -> Self {
         self.content.push(content.into());
         self
@@ -52,21 +119,70 @@ impl PageBuilder {
     }
 
     pub fn build(self) -> Page {
+        // Pages in subdirectories need to walk back up to reach shared files
+        let root = "../".repeat(self.slug.matches('/').count());
+
         Page {
             layout: self.layout,
             properties: LayoutProperties {
                 title: self.title,
                 slug: self.slug,
+                root,
+                date: self.date,
+                updated: self.updated,
+                tags: self.tags,
+                series: self.series,
+                comments: self.comments,
+                toc: self.toc,
             },
             content: self.content.join("\n"),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LayoutProperties {
     pub title: String,
     pub slug: String,
+    /// Relative path from this page back to the site root, e.g. `../`
+    pub root: String,
+    pub date: Option<PostDate>,
+    pub updated: Option<PostDate>,
+    pub tags: Vec<String>,
+    pub series: Option<SeriesNav>,
+    pub comments: Option<Comments>,
+    /// Shown above the content when not empty
+    pub toc: Vec<TocEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Link {
+    pub title: String,
+    /// Relative to the site root
+    pub href: String,
+}
+
+/// Where a post sits within its series, shown as a "Part N of M" box.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesNav {
+    pub name: String,
+    /// Relative to the site root
+    pub href: String,
+    /// One-based
+    pub part: usize,
+    pub total: usize,
+    pub previous: Option<Link>,
+    pub next: Option<Link>,
+}
+
+/// Replies to a post's Bluesky announcement, shown under the article.
+#[derive(Debug, Clone, Serialize)]
+pub struct Comments {
+    /// The announcement on `bsky.app`, where readers can reply
+    pub href: String,
+    pub count: usize,
+    /// Pre-rendered `<li>` per top-level reply
+    pub html: String,
 }
 
 #[derive(Debug, Clone)]
@@ -78,16 +194,36 @@ pub struct Page {
 
 pub struct SiteGenerator {
     pages: Vec<Page>,
+    /// Each post's content with shortcodes expanded, keyed by path
+    post_html: HashMap<PathBuf, String>,
+    feed_options: FeedOptions,
+    date_format: String,
+    /// Inject the dev server's reload script into every page
+    live_reload: bool,
 }
 
 #[async_trait]
 impl Service for SiteGenerator {
-    fn name(&self) -> &'static str {
-        "SiteGenerator"
-    }
+    const NAME: &'static str = "SiteGenerator";
+    const DEPENDENCIES: &'static [&'static str] = &[ContentSources::NAME, BlueSky::NAME];
 
     async fn init() -> Result<Self> {
-        Ok(Self { pages: Vec::new() })
+        Ok(Self {
+            pages: Vec::new(),
+            post_html: HashMap::new(),
+            feed_options: FeedOptions::default(),
+            date_format: PostDate::DEFAULT_FORMAT.to_string(),
+            live_reload: false,
+        })
+    }
+}
+
+#[async_trait]
+impl UpdateableService for SiteGenerator {
+    /// Replaces the pages with ones assembled from the loaded content and
+    /// Bluesky posts.
+    async fn update(&mut self, cx: &AppContext) -> Result<()> {
+        crate::build::add_pages(self, cx).await
     }
 }
 
@@ -96,6 +232,32 @@ impl SiteGenerator {
         PageBuilder::new(layout)
     }
 
+    pub fn set_feed_options(&mut self, feed_options: FeedOptions) {
+        self.feed_options = feed_options;
+    }
+
+    /// The `strftime` style format used to display dates.
+    pub fn date_format(&self) -> &str {
+        &self.date_format
+    }
+
+    pub fn set_date_format(&mut self, date_format: impl Into<String>) {
+        self.date_format = date_format.into();
+    }
+
+    pub fn set_live_reload(&mut self, live_reload: bool) {
+        self.live_reload = live_reload;
+    }
+
+    pub fn clear_pages(&mut self) {
+        self.pages.clear();
+        self.post_html.clear();
+    }
+
+    pub fn set_post_html(&mut self, post_html: HashMap<PathBuf, String>) {
+        self.post_html = post_html;
+    }
+
     pub fn add_page(&mut self, page: Page) {
         debug!("Adding page: {}", page.properties.title);
         self.pages.push(page);
@@ -148,113 +310,250 @@ impl SiteGenerator {
         Ok(())
     }
 
-    fn includes_str(&self) -> String {
-        debug!("Generating includes string");
-        let mut includes = String::new();
-        for style in crate::includes::includes().styles {
-            includes.push_str(&format!(r#"<link rel="stylesheet" href="{}">"#, style));
-        }
-        includes
-    }
-
     pub async fn generate(&self, cx: &AppContext) -> Result<()> {
         info!("Starting site generation");
         self.copy_includes(cx).await?;
+        highlight::write_stylesheet(&cx.output_dir())?;
+
+        let templates = Templates::load(&cx.templates_dir(), &self.date_format)?;
+        let site = &cx.config().site;
+        let template_hash = hash_dirs(&[cx.templates_dir(), cx.includes_dir()])?;
+
+        let previous = BuildCache::load(&cx.cache_dir());
+        let mut cache = BuildCache::new(template_hash);
+        let (mut rendered, mut skipped) = (0, 0);
 
         for page in &self.pages {
-            debug!("Rendering page: {}", page.properties.title);
-            let html = self.render(page);
-            let path = cx
-                .output_dir()
-                .join(&page.properties.slug)
-                .with_extension("html");
-            debug!("Writing file: {:?}", path);
-            cx.write_file(path, &html)?;
+            let output_path = PathBuf::from(&page.properties.slug).with_extension("html");
+            let key = output_path.to_string_lossy().to_string();
+            let hash = self.page_hash(&cache.template_hash, site, page)?;
+            let path = cx.output_dir().join(&output_path);
+
+            if previous.pages.get(&key) == Some(&hash) && path.is_file() {
+                debug!("Skipping unchanged page: {}", page.properties.title);
+                skipped += 1;
+            } else {
+                let html = self.render(&templates, site, page)?;
+                debug!("Writing file: {:?}", path);
+                cx.write_file(path, &html)?;
+                rendered += 1;
+            }
+
+            cache.pages.insert(key, hash);
         }
+
+        // Remove pages left over from posts, tags, etc. that no longer exist
+        let mut removed = 0;
+        for stale in previous.pages.keys() {
+            if !cache.pages.contains_key(stale) {
+                let path = cx.output_dir().join(stale);
+                if path.is_file() {
+                    debug!("Removing stale page: {:?}", path);
+                    fs::remove_file(&path)?;
+                    removed += 1;
+                }
+            }
+        }
+
+        info!(
+            "Rendered {} pages, skipped {} unchanged, removed {}",
+            rendered, skipped, removed
+        );
+
+        self.write_feeds(cx).await?;
+        cache.save(&cx.cache_dir())?;
+
         info!("Site generation complete");
         Ok(())
     }
 
-    fn render(&self, page: &Page) -> String {
+    /// Renders every page and the feeds in memory, failing on the first
+    /// error. Nothing is written to the output directory.
+    pub async fn check(&self, cx: &AppContext) -> Result<()> {
+        let templates = Templates::load(&cx.templates_dir(), &self.date_format)?;
+        for page in &self.pages {
+            self.render(&templates, &cx.config().site, page)
+                .with_context(|| format!("Failed to render page {:?}", page.properties.slug))?;
+        }
+
+        let content_sources = cx.content_sources().read().await;
+        let posts = content_sources.posts_collection().posts();
+        let feed = Feed::from_posts(cx, &posts, &self.post_html, &self.feed_options)?;
+        feed.to_atom()?;
+        feed.to_rss()?;
+        feed.to_json_feed()?;
+
+        info!(
+            "Checked {} pages and {} feed entries",
+            self.pages.len(),
+            feed.entries.len()
+        );
+        Ok(())
+    }
+
+    /// Hashes everything that affects how `page` renders.
+    fn page_hash(&self, template_hash: &str, site: &SiteMetadata, page: &Page) -> Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(template_hash.as_bytes());
+        hasher.update(&serde_json::to_vec(site)?);
+        hasher.update(page.layout.template_name().as_bytes());
+        hasher.update(&serde_json::to_vec(&page.properties)?);
+        hasher.update(page.content.as_bytes());
+        hasher.update(self.date_format.as_bytes());
+        hasher.update(&[self.live_reload as u8]);
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    async fn write_feeds(&self, cx: &AppContext) -> Result<()> {
+        let content_sources = cx.content_sources().read().await;
+        let posts = content_sources.posts_collection().posts();
+        let feed = Feed::from_posts(cx, &posts, &self.post_html, &self.feed_options)?;
+        info!("Writing feeds with {} entries", feed.entries.len());
+
+        let output_dir = cx.output_dir();
+        cx.write_file(output_dir.join(Feed::ATOM_PATH), &feed.to_atom()?)?;
+        cx.write_file(output_dir.join(Feed::RSS_PATH), &feed.to_rss()?)?;
+        cx.write_file(output_dir.join(Feed::JSON_PATH), &feed.to_json_feed()?)?;
+        Ok(())
+    }
+
+    fn render(&self, templates: &Templates, site: &SiteMetadata, page: &Page) -> Result<String> {
         debug!("Rendering page: {}", page.properties.title);
-        match page.layout {
-            Layout::Index => self.render_index(page),
-            Layout::Page => self.render_page(page),
+        let mut context = TemplateContext::new(site, page);
+        context.live_reload = self.live_reload.then_some(crate::server::LIVE_RELOAD_PATH);
+        templates.render(page.layout.template_name(), &context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ContextOptions;
+    use crate::utils::test_dir;
+    use std::{path::Path, sync::Arc};
+    use tempfile::TempDir;
+
+    /// A site in a temporary directory with copies of the real templates and
+    /// includes, so tests can change them.
+    async fn test_site() -> (TempDir, Arc<AppContext>) {
+        let dir = test_dir();
+        let root = dir.path();
+        let repo = Path::new(env!("CARGO_MANIFEST_DIR"));
+        copy_dir(&repo.join("templates"), &root.join("templates"));
+        copy_dir(&repo.join("src").join("includes"), &root.join("includes"));
+
+        let cx = AppContext::new(ContextOptions {
+            // Doesn't exist, so directories default to ones under `root`
+            config: Some(root.join("homebase.toml")),
+            includes_dir: Some(root.join("includes")),
+            cache_dir: Some(root.join("cache")),
+            offline: true,
+            ..ContextOptions::default()
+        })
+        .await
+        .unwrap();
+        (dir, cx)
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let destination = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &destination);
+            } else {
+                fs::copy(&path, &destination).unwrap();
+            }
         }
     }
 
-    fn head_template(&self, page: &Page) -> String {
-        format!(
-            r#"
-                <meta charset="UTF-8">
-                <meta name="viewport" content="width=device-width, initial-scale=1.0">
-                <link rel="icon" href="data:image/svg+xml,<svg xmlns=%22http://www.w3.org/2000/svg%22 viewBox=%220 0 100 100%22><text y=%22.9em%22 font-size=%2295%22>✌️</text></svg>">
-                <title>{}</title>
-                {}
-            "#,
-            page.properties.title,
-            self.includes_str()
-        )
-        .unindent()
+    async fn generate(cx: &AppContext, pages: &[(&str, &str)]) {
+        let mut site_generator = cx.site_generator().write().await;
+        site_generator.clear_pages();
+        for (slug, content) in pages {
+            let page = site_generator
+                .new_page(Layout::Page)
+                .title(*slug)
+                .slug(*slug)
+                .child(*content)
+                .build();
+            site_generator.add_page(page);
+        }
+        site_generator.generate(cx).await.unwrap();
     }
 
-    fn container_template(&self, content: &str) -> String {
-        format!(
-            r#"
-                <div class="container">
-                    {}
-                </div>
-            "#,
-            content
-        )
-        .unindent()
-    }
-
-    fn base_template(&self, page: &Page, content: &str) -> String {
-        format!(
-            r#"
-                <!DOCTYPE html>
-                <html lang="en">
-                <head>
-                    {}
-                </head>
-                <body>
-                    {}
-                </body>
-                </html>
-            "#,
-            self.head_template(page),
-            content
-        )
-        .unindent()
-    }
-
-    fn render_index(&self, page: &Page) -> String {
-        let content = format!(
-            r#"
-            <h1 class="headline-blue">{}</h1>
-            <ul>
-                {}
-            </ul>
-            "#,
-            page.properties.title, page.content
-        )
-        .unindent();
-        self.base_template(page, &content)
-    }
-
-    fn render_page(&self, page: &Page) -> String {
-        let content = format!(
-            r#"
-                <a href="index.html">&larr; Back Home</a>
-                <article>
-                    <h1>{}</h1>
-                    {}
-                </article>
-            "#,
-            page.properties.title, page.content
+    /// Marks an output page, so a later build overwriting it is visible.
+    fn mark(cx: &AppContext, slug: &str) {
+        fs::write(cx.output_dir().join(slug).with_extension("html"), "marked").unwrap();
+    }
+
+    fn is_marked(cx: &AppContext, slug: &str) -> bool {
+        fs::read_to_string(cx.output_dir().join(slug).with_extension("html")).unwrap() == "marked"
+    }
+
+    fn append(path: PathBuf, text: &str) {
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str(text);
+        fs::write(path, content).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_pages_whose_inputs_are_unchanged() {
+        let (_root, cx) = test_site().await;
+        generate(&cx, &[("first", "<p>One</p>"), ("second", "<p>Two</p>")]).await;
+        assert!(fs::read_to_string(cx.output_dir().join("first.html"))
+            .unwrap()
+            .contains("<p>One</p>"));
+        mark(&cx, "first");
+        mark(&cx, "second");
+
+        generate(
+            &cx,
+            &[("first", "<p>One</p>"), ("second", "<p>Changed</p>")],
         )
-        .unindent();
-        self.base_template(page, &content)
+        .await;
+        assert!(is_marked(&cx, "first"));
+        assert!(!is_marked(&cx, "second"));
+    }
+
+    #[tokio::test]
+    async fn template_and_include_changes_rerender_every_page() {
+        let (root, cx) = test_site().await;
+        let pages = [("first", "<p>One</p>"), ("tags/rust", "<p>Rust</p>")];
+        generate(&cx, &pages).await;
+
+        mark(&cx, "first");
+        mark(&cx, "tags/rust");
+        append(
+            root.path().join("templates").join("page.html"),
+            "\n{# changed #}\n",
+        );
+        generate(&cx, &pages).await;
+        assert!(!is_marked(&cx, "first"));
+        assert!(!is_marked(&cx, "tags/rust"));
+
+        mark(&cx, "first");
+        mark(&cx, "tags/rust");
+        append(
+            root.path().join("includes").join("global.css"),
+            "\n/* changed */\n",
+        );
+        generate(&cx, &pages).await;
+        assert!(!is_marked(&cx, "first"));
+        assert!(!is_marked(&cx, "tags/rust"));
+    }
+
+    #[tokio::test]
+    async fn removes_pages_that_are_no_longer_built() {
+        let (_root, cx) = test_site().await;
+        generate(&cx, &[("first", "<p>One</p>"), ("tags/old", "<p>Old</p>")]).await;
+        assert!(cx.output_dir().join("tags/old.html").is_file());
+
+        generate(&cx, &[("first", "<p>One</p>")]).await;
+        assert!(cx.output_dir().join("first.html").is_file());
+        assert!(!cx.output_dir().join("tags/old.html").exists());
+        // Files the build didn't write are left alone
+        assert!(cx.output_dir().join("global.css").is_file());
     }
 }
@@ -0,0 +1,666 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+use crate::context::AppContext;
+use crate::markdown::CALLOUT_KINDS;
+use crate::services::blue_sky::comments::thread_url;
+use crate::utils::html::escape_html;
+
+/// A `{{< name key="value" >}}` tag on its own line in a post, or a pair of
+/// them around a body closed with `{{< /name >}}`. Replaced with a
+/// placeholder while parsing and expanded once the site's services are up
+/// to date. Bodies stay in the post, between an opening and a closing
+/// placeholder, so they're rendered along with the rest of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shortcode {
+    pub name: String,
+    pub args: BTreeMap<String, String>,
+    /// The rendered HTML between the opening and closing tags, filled in
+    /// when the shortcode is expanded
+    pub body: Option<String>,
+    /// Line of the opening tag within the post file
+    pub line: usize,
+}
+
+impl Shortcode {
+    /// The HTML comment standing in for the shortcode at `index` until it's
+    /// expanded.
+    fn placeholder(index: usize) -> String {
+        format!("<!-- shortcode:{} -->", index)
+    }
+
+    /// The HTML comment after the body of the shortcode at `index`.
+    fn closing_placeholder(index: usize) -> String {
+        format!("<!-- /shortcode:{} -->", index)
+    }
+
+    pub fn arg(&self, key: &str) -> Option<&str> {
+        self.args.get(key).map(String::as_str)
+    }
+
+    /// An argument the handler declared as required, so validated to exist.
+    fn required(&self, key: &str) -> &str {
+        self.arg(key).unwrap_or_default()
+    }
+
+    fn body(&self) -> &str {
+        self.body.as_deref().unwrap_or_default()
+    }
+}
+
+/// What a shortcode handler can reach while rendering.
+pub struct ShortcodeContext<'a> {
+    pub app: &'a AppContext,
+    pub date_format: &'a str,
+}
+
+#[async_trait]
+pub trait ShortcodeHandler: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn required_args(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn optional_args(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Whether the shortcode wraps a body and needs a closing tag.
+    fn has_body(&self) -> bool {
+        false
+    }
+
+    /// Checks argument values while parsing, so mistakes are reported before
+    /// anything is fetched.
+    fn validate(&self, _shortcode: &Shortcode) -> Result<()> {
+        Ok(())
+    }
+
+    async fn render(&self, shortcode: &Shortcode, cx: &ShortcodeContext<'_>) -> Result<String>;
+}
+
+/// Every shortcode posts can use.
+const HANDLERS: &[&dyn ShortcodeHandler] = &[&BlueSkyPost, &Callout, &Gallery, &YouTube];
+
+fn handler(name: &str) -> Result<&'static dyn ShortcodeHandler> {
+    HANDLERS
+        .iter()
+        .copied()
+        .find(|handler| handler.name() == name)
+        .ok_or_else(|| {
+            let names = HANDLERS
+                .iter()
+                .map(|handler| handler.name())
+                .collect::<Vec<_>>();
+            anyhow!(
+                "unknown shortcode `{}`, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+enum Tag {
+    Open {
+        name: String,
+        args: BTreeMap<String, String>,
+    },
+    Close {
+        name: String,
+    },
+}
+
+impl Tag {
+    /// Parses a line holding only a shortcode tag. Returns `None` for any
+    /// other line.
+    fn parse(line: &str) -> Option<Result<Self>> {
+        let inner = line.strip_prefix("{{<")?.strip_suffix(">}}")?.trim();
+        Some(Self::parse_inner(inner))
+    }
+
+    fn parse_inner(inner: &str) -> Result<Self> {
+        if let Some(name) = inner.strip_prefix('/') {
+            return Ok(Self::Close {
+                name: name.trim().to_string(),
+            });
+        }
+
+        let (name, mut rest) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+        if name.is_empty() {
+            return Err(anyhow!("shortcode is missing its name"));
+        }
+
+        let mut args = BTreeMap::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+
+            let (key, after_key) = rest
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected `key=\"value\"` in `{}` shortcode", name))?;
+            let key = key.trim();
+            let after_key = after_key.trim_start().strip_prefix('"').ok_or_else(|| {
+                anyhow!(
+                    "the value of `{}` in `{}` shortcode must be quoted",
+                    key,
+                    name
+                )
+            })?;
+
+            let mut value = String::new();
+            let mut chars = after_key.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((_, '\\')) => value.extend(chars.next().map(|(_, c)| c)),
+                    Some((index, '"')) => break index,
+                    Some((_, c)) => value.push(c),
+                    None => {
+                        return Err(anyhow!(
+                            "the value of `{}` in `{}` shortcode is missing its closing quote",
+                            key,
+                            name
+                        ))
+                    }
+                }
+            };
+
+            if args.insert(key.to_string(), value).is_some() {
+                return Err(anyhow!("`{}` is set twice in `{}` shortcode", key, name));
+            }
+            rest = &after_key[end + 1..];
+        }
+
+        Ok(Self::Open {
+            name: name.to_string(),
+            args,
+        })
+    }
+}
+
+/// Replaces every shortcode tag in `content` with a placeholder, returning
+/// the new markdown and the shortcodes in order. Tags inside fenced code
+/// blocks are left alone.
+pub fn extract(content: &str, first_line: usize) -> Result<(String, Vec<Shortcode>)> {
+    let mut output = String::with_capacity(content.len());
+    let mut shortcodes = Vec::new();
+    let mut fence: Option<&str> = None;
+    // The shortcode whose body the current line is in
+    let mut open: Option<Shortcode> = None;
+
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        let line_number = first_line + index;
+        let trimmed = line.trim();
+
+        let tag = match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+                None
+            }
+            None => {
+                fence = ["```", "~~~"]
+                    .into_iter()
+                    .find(|marker| trimmed.starts_with(marker));
+                Tag::parse(trimmed)
+            }
+        };
+
+        let Some(tag) = tag else {
+            output.push_str(line);
+            continue;
+        };
+
+        let location = |message: String| anyhow!("line {}: {}", line_number, message);
+        match tag.map_err(|e| location(e.to_string()))? {
+            Tag::Open { name, args } => {
+                if let Some(open) = &open {
+                    return Err(location(format!(
+                        "`{}` can't be nested inside `{}` from line {}",
+                        name, open.name, open.line
+                    )));
+                }
+
+                let handler = handler(&name).map_err(|e| location(e.to_string()))?;
+                let shortcode = Shortcode {
+                    name,
+                    args,
+                    body: None,
+                    line: line_number,
+                };
+                validate(handler, &shortcode).map_err(|e| location(e.to_string()))?;
+
+                push_placeholder(&mut output, line, &Shortcode::placeholder(shortcodes.len()));
+                if handler.has_body() {
+                    open = Some(shortcode);
+                } else {
+                    shortcodes.push(shortcode);
+                }
+            }
+            Tag::Close { name } => match open.take() {
+                Some(shortcode) if shortcode.name == name => {
+                    push_placeholder(
+                        &mut output,
+                        line,
+                        &Shortcode::closing_placeholder(shortcodes.len()),
+                    );
+                    shortcodes.push(shortcode);
+                }
+                _ => {
+                    return Err(location(format!(
+                        "`{{{{< /{} >}}}}` doesn't close an open shortcode",
+                        name
+                    )))
+                }
+            },
+        }
+    }
+
+    if let Some(shortcode) = open {
+        return Err(anyhow!(
+            "line {}: `{}` is never closed with `{{{{< /{} >}}}}`",
+            shortcode.line,
+            shortcode.name,
+            shortcode.name
+        ));
+    }
+
+    Ok((output, shortcodes))
+}
+
+/// Removes the placeholders, and the bodies between them, from markdown
+/// returned by `extract`, leaving only the post's own text.
+pub fn strip(markdown: &str, shortcodes: &[Shortcode]) -> String {
+    let mut markdown = markdown.to_string();
+    for (index, shortcode) in shortcodes.iter().enumerate() {
+        let placeholder = Shortcode::placeholder(index);
+        let Some(start) = markdown.find(&placeholder) else {
+            continue;
+        };
+        let mut end = start + placeholder.len();
+        if handler(&shortcode.name).is_ok_and(|handler| handler.has_body()) {
+            let closing = Shortcode::closing_placeholder(index);
+            if let Some(offset) = markdown[end..].find(&closing) {
+                end += offset + closing.len();
+            }
+        }
+        markdown.replace_range(start..end, "");
+    }
+    markdown
+}
+
+/// Writes a placeholder as an HTML block, keeping the tag's indentation.
+fn push_placeholder(output: &mut String, line: &str, placeholder: &str) {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    output.push_str(&format!("{}{}\n", indent, placeholder));
+}
+
+fn validate(handler: &dyn ShortcodeHandler, shortcode: &Shortcode) -> Result<()> {
+    if let Some(missing) = handler
+        .required_args()
+        .iter()
+        .find(|arg| !shortcode.args.contains_key(**arg))
+    {
+        return Err(anyhow!(
+            "`{}` shortcode is missing `{}`",
+            shortcode.name,
+            missing
+        ));
+    }
+
+    let known = |arg: &str| {
+        handler.required_args().contains(&arg) || handler.optional_args().contains(&arg)
+    };
+    if let Some(unknown) = shortcode.args.keys().find(|arg| !known(arg)) {
+        return Err(anyhow!(
+            "`{}` shortcode has no `{}` argument",
+            shortcode.name,
+            unknown
+        ));
+    }
+
+    handler.validate(shortcode)
+}
+
+/// Replaces the placeholders in a post's HTML with each shortcode's output,
+/// passing along the HTML between a body's placeholders.
+pub async fn expand(
+    html: &str,
+    shortcodes: &[Shortcode],
+    cx: &ShortcodeContext<'_>,
+) -> Result<String> {
+    let mut html = html.to_string();
+    for (index, shortcode) in shortcodes.iter().enumerate() {
+        let handler = handler(&shortcode.name)?;
+        let missing = || {
+            anyhow!(
+                "line {}: `{}` shortcode is missing from the rendered post",
+                shortcode.line,
+                shortcode.name
+            )
+        };
+
+        let placeholder = Shortcode::placeholder(index);
+        let start = html.find(&placeholder).ok_or_else(missing)?;
+        let mut end = start + placeholder.len();
+        let mut shortcode = shortcode.clone();
+        if handler.has_body() {
+            let closing = Shortcode::closing_placeholder(index);
+            let body_end = html[end..]
+                .find(&closing)
+                .map(|offset| end + offset)
+                .ok_or_else(missing)?;
+            shortcode.body = Some(html[end..body_end].trim().to_string());
+            end = body_end + closing.len();
+        }
+
+        let rendered = handler.render(&shortcode, cx).await.with_context(|| {
+            format!(
+                "line {}: failed to render `{}` shortcode",
+                shortcode.line, shortcode.name
+            )
+        })?;
+        html.replace_range(start..end, &rendered);
+    }
+    Ok(html)
+}
+
+/// The posts embedded by `bluesky` shortcodes, which the Bluesky service
+/// fetches into its cache on update.
+pub fn bluesky_uris(shortcodes: &[Shortcode]) -> impl Iterator<Item = &str> {
+    shortcodes
+        .iter()
+        .filter(|shortcode| shortcode.name == BlueSkyPost.name())
+        .map(|shortcode| shortcode.required("uri"))
+}
+
+/// `{{< bluesky uri="at://..." >}}` embeds a post from the Bluesky cache.
+/// Rendering never goes to the network, so until the post has been fetched,
+/// or when it can't be, it falls back to a link.
+struct BlueSkyPost;
+
+#[async_trait]
+impl ShortcodeHandler for BlueSkyPost {
+    fn name(&self) -> &'static str {
+        "bluesky"
+    }
+
+    fn required_args(&self) -> &'static [&'static str] {
+        &["uri"]
+    }
+
+    fn validate(&self, shortcode: &Shortcode) -> Result<()> {
+        let uri = shortcode.required("uri");
+        if !uri.starts_with("at://") || !uri.contains("/app.bsky.feed.post/") {
+            return Err(anyhow!(
+                "`uri` should be a post's AT URI, like at://did:plc:.../app.bsky.feed.post/..., got {:?}",
+                uri
+            ));
+        }
+        Ok(())
+    }
+
+    async fn render(&self, shortcode: &Shortcode, cx: &ShortcodeContext<'_>) -> Result<String> {
+        let uri = shortcode.required("uri");
+        let blue_sky = cx.app.blue_sky().read().await;
+
+        Ok(match blue_sky.post(uri) {
+            Some(post) => post.render(cx.date_format),
+            None => format!(
+                "<blockquote class='note'><a href='{}'>View this post on Bluesky</a></blockquote>",
+                escape_html(&thread_url(uri))
+            ),
+        })
+    }
+}
+
+/// `{{< callout kind="warning" title="..." >}}` wraps its body in an aside.
+struct Callout;
+
+#[async_trait]
+impl ShortcodeHandler for Callout {
+    fn name(&self) -> &'static str {
+        "callout"
+    }
+
+    fn optional_args(&self) -> &'static [&'static str] {
+        &["kind", "title"]
+    }
+
+    fn has_body(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, shortcode: &Shortcode) -> Result<()> {
+        match shortcode.arg("kind") {
+            Some(kind) if !CALLOUT_KINDS.contains(&kind) => Err(anyhow!(
+                "`kind` should be one of {}, got {:?}",
+                CALLOUT_KINDS.join(", "),
+                kind
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    async fn render(&self, shortcode: &Shortcode, _cx: &ShortcodeContext<'_>) -> Result<String> {
+        let kind = shortcode.arg("kind").unwrap_or("note");
+        let title = shortcode
+            .arg("title")
+            .map(|title| format!("<p class='callout-title'>{}</p>", escape_html(title)))
+            .unwrap_or_default();
+        Ok(format!(
+            "<aside class='callout {}'>{}{}</aside>",
+            kind,
+            title,
+            shortcode.body()
+        ))
+    }
+}
+
+/// `{{< gallery >}}` lays out the images in its body in a grid.
+struct Gallery;
+
+#[async_trait]
+impl ShortcodeHandler for Gallery {
+    fn name(&self) -> &'static str {
+        "gallery"
+    }
+
+    fn optional_args(&self) -> &'static [&'static str] {
+        &["columns"]
+    }
+
+    fn has_body(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, shortcode: &Shortcode) -> Result<()> {
+        match shortcode.arg("columns").map(str::parse::<u8>) {
+            Some(Ok(1..=6)) | None => Ok(()),
+            Some(_) => Err(anyhow!("`columns` should be a number from 1 to 6")),
+        }
+    }
+
+    async fn render(&self, shortcode: &Shortcode, _cx: &ShortcodeContext<'_>) -> Result<String> {
+        let columns = shortcode.arg("columns").unwrap_or("3");
+        Ok(format!(
+            "<div class='gallery' style='--columns: {}'>{}</div>",
+            columns,
+            shortcode.body()
+        ))
+    }
+}
+
+/// `{{< youtube id="..." >}}` embeds a video without YouTube's tracking
+/// cookies.
+struct YouTube;
+
+#[async_trait]
+impl ShortcodeHandler for YouTube {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn required_args(&self) -> &'static [&'static str] {
+        &["id"]
+    }
+
+    fn optional_args(&self) -> &'static [&'static str] {
+        &["title", "start"]
+    }
+
+    fn validate(&self, shortcode: &Shortcode) -> Result<()> {
+        let id = shortcode.required("id");
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!("`id` should be a YouTube video id, got {:?}", id));
+        }
+        if let Some(start) = shortcode.arg("start") {
+            start
+                .parse::<u32>()
+                .map_err(|_| anyhow!("`start` should be a number of seconds, got {:?}", start))?;
+        }
+        Ok(())
+    }
+
+    async fn render(&self, shortcode: &Shortcode, _cx: &ShortcodeContext<'_>) -> Result<String> {
+        let start = shortcode
+            .arg("start")
+            .map(|start| format!("?start={}", start))
+            .unwrap_or_default();
+        Ok(format!(
+            "<div class='embed video'><iframe src='https://www.youtube-nocookie.com/embed/{}{}' title='{}' loading='lazy' allow='encrypted-media; picture-in-picture' allowfullscreen></iframe></div>",
+            shortcode.required("id"),
+            start,
+            escape_html(shortcode.arg("title").unwrap_or("YouTube video"))
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ContextOptions;
+    use crate::markdown::{Markdown, MarkdownExtensions};
+
+    fn extract(content: &str) -> Result<(String, Vec<Shortcode>)> {
+        super::extract(content, 1)
+    }
+
+    /// Renders `content` as a post and expands its shortcodes.
+    async fn render(content: &str, extensions: &MarkdownExtensions) -> (String, Vec<String>) {
+        let app = AppContext::new(ContextOptions::default()).await.unwrap();
+        let cx = ShortcodeContext {
+            app: &app,
+            date_format: "%Y-%m-%d",
+        };
+        let rendered = Markdown::parse(content, 1, extensions).unwrap();
+        let html = expand(&rendered.html, &rendered.shortcodes, &cx)
+            .await
+            .unwrap();
+        let toc = rendered.toc.into_iter().map(|entry| entry.id).collect();
+        (html, toc)
+    }
+
+    #[test]
+    fn replaces_shortcodes_with_placeholders() {
+        let (markdown, shortcodes) = extract(
+            "Intro\n\n{{< youtube id=\"dQw4w9WgXcQ\" title=\"A \\\"song\\\"\" >}}\n\n{{< callout kind=\"tip\" >}}\nBody\n{{< /callout >}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            markdown,
+            "Intro\n\n<!-- shortcode:0 -->\n\n<!-- shortcode:1 -->\nBody\n<!-- /shortcode:1 -->\n"
+        );
+        assert_eq!(shortcodes.len(), 2);
+        assert_eq!(shortcodes[0].name, "youtube");
+        assert_eq!(shortcodes[0].arg("title"), Some("A \"song\""));
+        assert_eq!(shortcodes[0].line, 3);
+        assert_eq!(shortcodes[1].line, 5);
+    }
+
+    #[test]
+    fn ignores_tags_in_code_fences() {
+        let content = "```md\n{{< nope >}}\n```\n";
+        let (markdown, shortcodes) = extract(content).unwrap();
+        assert_eq!(markdown, content);
+        assert!(shortcodes.is_empty());
+    }
+
+    #[test]
+    fn reports_mistakes_with_their_line() {
+        let error = |content| extract(content).unwrap_err().to_string();
+
+        assert_eq!(
+            error("Intro\n\n{{< vimeo id=\"1\" >}}\n"),
+            "line 3: unknown shortcode `vimeo`, expected one of bluesky, callout, gallery, youtube"
+        );
+        assert_eq!(
+            error("{{< youtube >}}\n"),
+            "line 1: `youtube` shortcode is missing `id`"
+        );
+        assert_eq!(
+            error("{{< youtube id=\"x\" autoplay=\"1\" >}}\n"),
+            "line 1: `youtube` shortcode has no `autoplay` argument"
+        );
+        assert_eq!(
+            error("{{< gallery >}}\n![](a.png)\n"),
+            "line 1: `gallery` is never closed with `{{< /gallery >}}`"
+        );
+        assert_eq!(
+            error("{{< callout >}}\n{{< gallery >}}\n"),
+            "line 2: `gallery` can't be nested inside `callout` from line 1"
+        );
+        assert_eq!(
+            error("{{< /callout >}}\n"),
+            "line 1: `{{< /callout >}}` doesn't close an open shortcode"
+        );
+    }
+
+    #[tokio::test]
+    async fn expands_placeholders() {
+        let (html, _) = render(
+            "{{< callout kind=\"warning\" title=\"<Careful>\" >}}\nHot\n{{< /callout >}}\n{{< youtube id=\"abc_123\" start=\"30\" >}}\n",
+            &MarkdownExtensions::default(),
+        )
+        .await;
+
+        assert!(html.contains(
+            "<aside class='callout warning'><p class='callout-title'>&lt;Careful&gt;</p><p>Hot</p></aside>"
+        ));
+        assert!(html.contains("src='https://www.youtube-nocookie.com/embed/abc_123?start=30'"));
+        assert!(!html.contains("shortcode:"));
+    }
+
+    #[tokio::test]
+    async fn bodies_share_heading_ids_and_footnotes_with_the_post() {
+        let extensions = MarkdownExtensions {
+            footnotes: Some(true),
+            ..MarkdownExtensions::default()
+        };
+        let (html, toc) = render(
+            "## Setup\n\n{{< callout >}}\n## Setup\n\nInside[^inside].\n{{< /callout >}}\n\nOutside[^outside].\n\n[^inside]: In the callout.\n\n[^outside]: After it.\n",
+            &extensions,
+        )
+        .await;
+
+        assert_eq!(toc, ["setup", "setup-1"]);
+        let callout = &html[html.find("<aside").unwrap()..html.find("</aside>").unwrap()];
+        assert!(callout.contains("<h2 id=\"setup-1\">"));
+        assert!(callout.contains("<a href='#fn-1'>1</a>"));
+        assert!(!callout.contains("footnotes"));
+
+        assert_eq!(html.matches("<section class='footnotes'").count(), 1);
+        assert_eq!(html.matches("id='fn-1'").count(), 1);
+        assert!(html.contains("<a href='#fn-2'>2</a>"));
+        assert!(html.find("<section class='footnotes'").unwrap() > html.find("</aside>").unwrap());
+    }
+}
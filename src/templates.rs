@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use minijinja::{path_loader, Environment, Error, ErrorKind};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::config::SiteMetadata;
+use crate::markdown::{slugify, PostDate};
+use crate::services::site_generator::{LayoutProperties, Page};
+
+/// Layout templates loaded from the `templates/` directory.
+///
+/// Templates are plain minijinja files, so layouts can `{% extends %}` each
+/// other and pull in partials with `{% include %}` without recompiling.
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    /// Loads templates from `templates_dir`. `date_format` is the default
+    /// format used by the `date` filter.
+    pub fn load(templates_dir: &Path, date_format: &str) -> Result<Self> {
+        if !templates_dir.is_dir() {
+            return Err(anyhow!(
+                "Templates directory does not exist: {:?}",
+                templates_dir
+            ));
+        }
+
+        debug!("Loading templates from {:?}", templates_dir);
+        let mut env = Environment::new();
+        env.set_loader(path_loader(templates_dir));
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_filter("slugify", |value: String| slugify(&value));
+
+        // `{{ page.date | date }}` or `{{ page.date | date("%Y") }}`
+        let default_date_format = date_format.to_string();
+        env.add_filter(
+            "date",
+            move |value: String, format: Option<String>| -> Result<String, Error> {
+                let date = PostDate::parse(&value)
+                    .map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))?;
+                Ok(date.format(format.as_deref().unwrap_or(&default_date_format)))
+            },
+        );
+
+        Ok(Self { env })
+    }
+
+    pub fn render(&self, name: &str, context: &TemplateContext) -> Result<String> {
+        let template = self
+            .env
+            .get_template(name)
+            .with_context(|| format!("Failed to load template {:?}", name))?;
+
+        template
+            .render(context)
+            .with_context(|| format!("Failed to render template {:?}", name))
+    }
+}
+
+/// The values available to every layout template.
+#[derive(Debug, Serialize)]
+pub struct TemplateContext<'a> {
+    pub site: &'a SiteMetadata,
+    pub page: &'a LayoutProperties,
+    pub content: &'a str,
+    pub styles: Vec<&'static str>,
+    /// The dev server's reload endpoint, set when building for it
+    pub live_reload: Option<&'static str>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(site: &'a SiteMetadata, page: &'a Page) -> Self {
+        Self {
+            site,
+            page: &page.properties,
+            content: &page.content,
+            styles: crate::includes::includes().styles,
+            live_reload: None,
+        }
+    }
+}
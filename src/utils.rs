@@ -1 +1,11 @@
 mod fs;
+pub mod html;
+
+/// A new temporary directory for a test, deleted when the handle is dropped.
+#[cfg(test)]
+pub fn test_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("homebase-test-")
+        .tempdir()
+        .unwrap()
+}